@@ -0,0 +1,182 @@
+// Copyright 2021 The Druid Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Gamepad / game-controller input.
+//!
+//! This mirrors the keyboard path: vendor-specific button and axis codes from
+//! the backend poller are normalized into stable [`Button`]/[`Axis`] enums,
+//! the same way `hardware_keycode_to_code` normalizes scancodes, and delivered
+//! to the handler as [`GamepadEvent`]s tagged with the originating device.
+
+/// An opaque identifier distinguishing connected controllers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GamepadId(pub usize);
+
+/// A normalized controller button, following the common layout other toolkits
+/// expose.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Button {
+    /// Bottom action button (A / Cross).
+    South,
+    /// Right action button (B / Circle).
+    East,
+    /// Top action button (Y / Triangle).
+    North,
+    /// Left action button (X / Square).
+    West,
+    LeftShoulder,
+    RightShoulder,
+    LeftTrigger,
+    RightTrigger,
+    LeftThumb,
+    RightThumb,
+    Select,
+    Start,
+    Mode,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+    /// A button the backend reported that we don't have a stable name for.
+    Unknown,
+}
+
+/// A normalized analog axis.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Axis {
+    LeftStickX,
+    LeftStickY,
+    RightStickX,
+    RightStickY,
+    LeftTrigger,
+    RightTrigger,
+    Unknown,
+}
+
+/// A controller input event, delivered alongside keyboard/mouse events.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GamepadEvent {
+    /// A controller was connected.
+    Connected { device: GamepadId },
+    /// A controller was disconnected.
+    Disconnected { device: GamepadId },
+    /// A button was pressed.
+    ButtonPressed { device: GamepadId, button: Button },
+    /// A button was released.
+    ButtonReleased { device: GamepadId, button: Button },
+    /// An analog axis moved. `value` is normalized to `-1.0..=1.0`.
+    AxisChanged {
+        device: GamepadId,
+        axis: Axis,
+        value: f64,
+    },
+}
+
+#[cfg(feature = "gamepad")]
+pub use backend::GamepadPoller;
+
+#[cfg(feature = "gamepad")]
+mod backend {
+    use super::{Axis, Button, GamepadEvent, GamepadId};
+
+    /// Polls the platform for controller input via `gilrs`.
+    pub struct GamepadPoller {
+        gilrs: gilrs::Gilrs,
+    }
+
+    impl GamepadPoller {
+        /// Create a poller, or return `None` if no gamepad subsystem is
+        /// available on this platform.
+        pub fn new() -> Option<GamepadPoller> {
+            gilrs::Gilrs::new().ok().map(|gilrs| GamepadPoller { gilrs })
+        }
+
+        /// Drain all pending controller events, translating them into the
+        /// normalized [`GamepadEvent`] space.
+        pub fn poll(&mut self) -> Vec<GamepadEvent> {
+            let mut events = Vec::new();
+            while let Some(gilrs::Event { id, event, .. }) = self.gilrs.next_event() {
+                let device = GamepadId(usize::from(id));
+                match event {
+                    gilrs::EventType::Connected => {
+                        events.push(GamepadEvent::Connected { device });
+                    }
+                    gilrs::EventType::Disconnected => {
+                        events.push(GamepadEvent::Disconnected { device });
+                    }
+                    gilrs::EventType::ButtonPressed(button, _) => {
+                        events.push(GamepadEvent::ButtonPressed {
+                            device,
+                            button: normalize_button(button),
+                        });
+                    }
+                    gilrs::EventType::ButtonReleased(button, _) => {
+                        events.push(GamepadEvent::ButtonReleased {
+                            device,
+                            button: normalize_button(button),
+                        });
+                    }
+                    gilrs::EventType::AxisChanged(axis, value, _) => {
+                        events.push(GamepadEvent::AxisChanged {
+                            device,
+                            axis: normalize_axis(axis),
+                            value: value as f64,
+                        });
+                    }
+                    // ButtonChanged (analog button pressure) and dropped events
+                    // don't map onto our model; ignore them.
+                    _ => {}
+                }
+            }
+            events
+        }
+    }
+
+    fn normalize_button(button: gilrs::Button) -> Button {
+        use gilrs::Button as B;
+        match button {
+            B::South => Button::South,
+            B::East => Button::East,
+            B::North => Button::North,
+            B::West => Button::West,
+            B::LeftTrigger => Button::LeftShoulder,
+            B::RightTrigger => Button::RightShoulder,
+            B::LeftTrigger2 => Button::LeftTrigger,
+            B::RightTrigger2 => Button::RightTrigger,
+            B::LeftThumb => Button::LeftThumb,
+            B::RightThumb => Button::RightThumb,
+            B::Select => Button::Select,
+            B::Start => Button::Start,
+            B::Mode => Button::Mode,
+            B::DPadUp => Button::DPadUp,
+            B::DPadDown => Button::DPadDown,
+            B::DPadLeft => Button::DPadLeft,
+            B::DPadRight => Button::DPadRight,
+            _ => Button::Unknown,
+        }
+    }
+
+    fn normalize_axis(axis: gilrs::Axis) -> Axis {
+        use gilrs::Axis as A;
+        match axis {
+            A::LeftStickX => Axis::LeftStickX,
+            A::LeftStickY => Axis::LeftStickY,
+            A::RightStickX => Axis::RightStickX,
+            A::RightStickY => Axis::RightStickY,
+            A::LeftZ => Axis::LeftTrigger,
+            A::RightZ => Axis::RightTrigger,
+            _ => Axis::Unknown,
+        }
+    }
+}
@@ -0,0 +1,423 @@
+// Copyright 2021 The Druid Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A declarative keybinding layer that maps [`KeyEvent`]s to named actions.
+//!
+//! Bindings are described by a small textual table, e.g.
+//!
+//! ```text
+//! ctrl+shift+p => command_palette
+//! g g          => goto_top
+//! ```
+//!
+//! Each line is a sequence of one or more chords followed by `=>` and the
+//! action name. A chord is a trigger key optionally preceded by modifier
+//! tokens joined with `+`. Matching keys off [`KeyEvent::code`] for named and
+//! alphanumeric triggers, so bindings are layout independent, and off
+//! [`KeyEvent::key`] for any trigger that is only expressible as a character.
+
+use std::time::{Duration, Instant};
+
+use crate::keyboard::{KbKey, KeyEvent, Modifiers};
+use crate::keyboard_types::Code;
+
+/// The default timeout after which an incomplete chord sequence is abandoned.
+const DEFAULT_CHORD_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// The key part of a chord, matched against either the physical code or the
+/// logical character of an event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Trigger {
+    /// A layout-independent physical key.
+    Code(Code),
+    /// A character produced by the active layout.
+    Character(String),
+}
+
+/// A single chord: a set of modifiers plus a trigger key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Chord {
+    mods: Modifiers,
+    trigger: Trigger,
+}
+
+impl Chord {
+    /// Whether this chord matches `event`.
+    fn matches(&self, event: &KeyEvent) -> bool {
+        if event.mods != self.mods {
+            return false;
+        }
+        match &self.trigger {
+            Trigger::Code(code) => event.code == *code,
+            Trigger::Character(s) => matches!(&event.key, KbKey::Character(c) if c == s),
+        }
+    }
+}
+
+/// A binding from a chord sequence to a named action.
+#[derive(Debug, Clone)]
+struct Binding {
+    sequence: Vec<Chord>,
+    action: String,
+}
+
+/// A parsed keymap that resolves [`KeyEvent`]s into named actions.
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    bindings: Vec<Binding>,
+    timeout: Duration,
+    /// Whether held keys should re-fire. When `false`, events with `repeat` set
+    /// are ignored.
+    allow_repeat: bool,
+    pending: Vec<KeyEvent>,
+    last_press: Option<Instant>,
+}
+
+impl Keymap {
+    /// Parse a binding table, returning the keymap or a description of the first
+    /// malformed line.
+    pub fn parse(table: &str) -> Result<Keymap, String> {
+        let mut bindings = Vec::new();
+        for (n, line) in table.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (chords, action) = line
+                .split_once("=>")
+                .ok_or_else(|| format!("line {}: missing `=>`", n + 1))?;
+            let action = action.trim();
+            if action.is_empty() {
+                return Err(format!("line {}: missing action", n + 1));
+            }
+            let sequence = chords
+                .split_whitespace()
+                .map(parse_chord)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| format!("line {}: {}", n + 1, e))?;
+            if sequence.is_empty() {
+                return Err(format!("line {}: missing chords", n + 1));
+            }
+            bindings.push(Binding {
+                sequence,
+                action: action.to_owned(),
+            });
+        }
+        Ok(Keymap {
+            bindings,
+            timeout: DEFAULT_CHORD_TIMEOUT,
+            allow_repeat: false,
+            pending: Vec::new(),
+            last_press: None,
+        })
+    }
+
+    /// Set the multi-key chord timeout.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Set whether held keys (events with `repeat` set) re-fire actions.
+    pub fn set_allow_repeat(&mut self, allow_repeat: bool) {
+        self.allow_repeat = allow_repeat;
+    }
+
+    /// Replace the bindings in place, preserving the configured timeout and
+    /// clearing any pending prefix. This is the reload-at-runtime entry point.
+    pub fn reload(&mut self, table: &str) -> Result<(), String> {
+        let parsed = Keymap::parse(table)?;
+        self.bindings = parsed.bindings;
+        self.pending.clear();
+        self.last_press = None;
+        Ok(())
+    }
+
+    /// Feed a key event and return the matched action, if the event completes a
+    /// binding. Partial matches are remembered as a pending prefix until the
+    /// next key, or until the chord times out.
+    pub fn handle(&mut self, event: &KeyEvent) -> Option<String> {
+        self.handle_at(event, Instant::now())
+    }
+
+    /// Like [`Keymap::handle`] but with an explicit timestamp, so callers can
+    /// drive timing deterministically.
+    pub fn handle_at(&mut self, event: &KeyEvent, now: Instant) -> Option<String> {
+        if event.repeat && !self.allow_repeat {
+            return None;
+        }
+        // Drop a stale prefix if the user paused for longer than the timeout.
+        if let Some(last) = self.last_press {
+            if now.duration_since(last) > self.timeout {
+                self.pending.clear();
+            }
+        }
+        self.last_press = Some(now);
+
+        // A bare modifier press is never a trigger on its own; ignore it so the
+        // pending prefix survives until the real key arrives.
+        if is_modifier_press(event) {
+            return None;
+        }
+        self.pending.push(event.clone());
+
+        // An exact match wins and resets the prefix. We compare each binding
+        // chord against the event that produced the pending entry via
+        // [`Chord::matches`]: a `Code` chord checks only the physical code,
+        // whatever character the layout produced, and a `Character` chord
+        // checks only the logical key.
+        if let Some(binding) = self
+            .bindings
+            .iter()
+            .find(|b| sequence_matches(&b.sequence, &self.pending))
+        {
+            let action = binding.action.clone();
+            self.pending.clear();
+            return Some(action);
+        }
+
+        // If the current prefix is still a prefix of some binding, keep waiting;
+        // otherwise it's a dead end, so reset.
+        let is_prefix = self
+            .bindings
+            .iter()
+            .any(|b| is_sequence_prefix(&b.sequence, &self.pending));
+        if !is_prefix {
+            self.pending.clear();
+        }
+        None
+    }
+}
+
+/// Whether a binding sequence matches the pending events exactly.
+fn sequence_matches(sequence: &[Chord], pending: &[KeyEvent]) -> bool {
+    sequence.len() == pending.len()
+        && sequence.iter().zip(pending).all(|(chord, ev)| chord.matches(ev))
+}
+
+/// Whether the pending events are a (possibly partial) prefix of a binding.
+fn is_sequence_prefix(sequence: &[Chord], pending: &[KeyEvent]) -> bool {
+    sequence.len() >= pending.len()
+        && sequence.iter().zip(pending).all(|(chord, ev)| chord.matches(ev))
+}
+
+/// Whether an event is a bare modifier key press.
+fn is_modifier_press(event: &KeyEvent) -> bool {
+    matches!(
+        event.key,
+        KbKey::Shift | KbKey::Control | KbKey::Alt | KbKey::Meta
+    )
+}
+
+/// Parse a single `mod+mod+key` chord token.
+fn parse_chord(token: &str) -> Result<Chord, String> {
+    let mut mods = Modifiers::empty();
+    let mut parts = token.split('+').peekable();
+    let mut key = None;
+    while let Some(part) = parts.next() {
+        // The last `+`-separated part is the trigger; everything before is a
+        // modifier.
+        if parts.peek().is_none() {
+            key = Some(part);
+            break;
+        }
+        mods |= parse_modifier(part)?;
+    }
+    let key = key.ok_or_else(|| format!("empty chord in `{}`", token))?;
+    Ok(Chord {
+        mods,
+        trigger: parse_trigger(key)?,
+    })
+}
+
+/// Map a modifier token to a `Modifiers` flag, honoring platform aliases.
+fn parse_modifier(token: &str) -> Result<Modifiers, String> {
+    Ok(match token.to_ascii_lowercase().as_str() {
+        "ctrl" | "control" => Modifiers::CONTROL,
+        "shift" => Modifiers::SHIFT,
+        "alt" | "option" => Modifiers::ALT,
+        "meta" | "super" | "win" => Modifiers::META,
+        // `cmd` is Super/Meta on macOS and Ctrl everywhere else.
+        "cmd" => {
+            if cfg!(target_os = "macos") {
+                Modifiers::META
+            } else {
+                Modifiers::CONTROL
+            }
+        }
+        other => return Err(format!("unknown modifier `{}`", other)),
+    })
+}
+
+/// Map a trigger token to a [`Trigger`]. Named keys and single alphanumeric
+/// characters resolve to a layout-independent [`Code`]; anything else becomes a
+/// character trigger.
+fn parse_trigger(token: &str) -> Result<Trigger, String> {
+    if let Some(code) = named_code(token) {
+        return Ok(Trigger::Code(code));
+    }
+    if token.chars().count() == 1 {
+        return Ok(Trigger::Character(token.to_owned()));
+    }
+    Err(format!("unknown key `{}`", token))
+}
+
+/// Resolve a key name (or single alphanumeric) to a physical [`Code`].
+fn named_code(token: &str) -> Option<Code> {
+    let lower = token.to_ascii_lowercase();
+    Some(match lower.as_str() {
+        "enter" | "return" => Code::Enter,
+        "tab" => Code::Tab,
+        "esc" | "escape" => Code::Escape,
+        "space" => Code::Space,
+        "up" => Code::ArrowUp,
+        "down" => Code::ArrowDown,
+        "left" => Code::ArrowLeft,
+        "right" => Code::ArrowRight,
+        "home" => Code::Home,
+        "end" => Code::End,
+        "pageup" => Code::PageUp,
+        "pagedown" => Code::PageDown,
+        "backspace" => Code::Backspace,
+        "delete" | "del" => Code::Delete,
+        _ => {
+            let mut chars = lower.chars();
+            let c = chars.next()?;
+            if chars.next().is_some() {
+                return None;
+            }
+            match c {
+                'a'..='z' => letter_code(c),
+                '0'..='9' => digit_code(c),
+                _ => return None,
+            }
+        }
+    })
+}
+
+fn letter_code(c: char) -> Code {
+    match c {
+        'a' => Code::KeyA,
+        'b' => Code::KeyB,
+        'c' => Code::KeyC,
+        'd' => Code::KeyD,
+        'e' => Code::KeyE,
+        'f' => Code::KeyF,
+        'g' => Code::KeyG,
+        'h' => Code::KeyH,
+        'i' => Code::KeyI,
+        'j' => Code::KeyJ,
+        'k' => Code::KeyK,
+        'l' => Code::KeyL,
+        'm' => Code::KeyM,
+        'n' => Code::KeyN,
+        'o' => Code::KeyO,
+        'p' => Code::KeyP,
+        'q' => Code::KeyQ,
+        'r' => Code::KeyR,
+        's' => Code::KeyS,
+        't' => Code::KeyT,
+        'u' => Code::KeyU,
+        'v' => Code::KeyV,
+        'w' => Code::KeyW,
+        'x' => Code::KeyX,
+        'y' => Code::KeyY,
+        _ => Code::KeyZ,
+    }
+}
+
+fn digit_code(c: char) -> Code {
+    match c {
+        '0' => Code::Digit0,
+        '1' => Code::Digit1,
+        '2' => Code::Digit2,
+        '3' => Code::Digit3,
+        '4' => Code::Digit4,
+        '5' => Code::Digit5,
+        '6' => Code::Digit6,
+        '7' => Code::Digit7,
+        '8' => Code::Digit8,
+        _ => Code::Digit9,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyboard::KeyState;
+    use crate::keyboard_types::Location;
+
+    /// Build a key-down event for the given physical code, producing `ch` as its
+    /// layout character, with the supplied modifiers held.
+    fn press(code: Code, ch: &str, mods: Modifiers) -> KeyEvent {
+        KeyEvent {
+            key: KbKey::Character(ch.to_owned()),
+            code,
+            location: Location::Standard,
+            mods,
+            repeat: false,
+            is_composing: false,
+            state: KeyState::Down,
+        }
+    }
+
+    #[test]
+    fn single_chord_with_modifiers() {
+        let mut keymap = Keymap::parse("ctrl+shift+p => palette").unwrap();
+        // Wrong modifiers don't fire.
+        assert_eq!(keymap.handle(&press(Code::KeyP, "p", Modifiers::CONTROL)), None);
+        // The exact chord does.
+        let mods = Modifiers::CONTROL | Modifiers::SHIFT;
+        assert_eq!(
+            keymap.handle(&press(Code::KeyP, "P", mods)).as_deref(),
+            Some("palette")
+        );
+    }
+
+    #[test]
+    fn multi_chord_sequence() {
+        let mut keymap = Keymap::parse("g g => goto_top").unwrap();
+        // The first `g` is only a prefix, so nothing fires yet.
+        assert_eq!(keymap.handle(&press(Code::KeyG, "g", Modifiers::empty())), None);
+        // The second completes the sequence.
+        assert_eq!(
+            keymap
+                .handle(&press(Code::KeyG, "g", Modifiers::empty()))
+                .as_deref(),
+            Some("goto_top")
+        );
+    }
+
+    #[test]
+    fn dead_end_resets_prefix() {
+        let mut keymap = Keymap::parse("g g => goto_top").unwrap();
+        assert_eq!(keymap.handle(&press(Code::KeyG, "g", Modifiers::empty())), None);
+        // A non-matching key clears the prefix...
+        assert_eq!(keymap.handle(&press(Code::KeyX, "x", Modifiers::empty())), None);
+        // ...so a following `g` is a fresh prefix, not a completion.
+        assert_eq!(keymap.handle(&press(Code::KeyG, "g", Modifiers::empty())), None);
+    }
+
+    #[test]
+    fn chord_matches_independent_of_layout_character() {
+        // The binding is by physical code, so an event carrying a different
+        // character (e.g. a non-Latin layout) still matches.
+        let mut keymap = Keymap::parse("ctrl+s => save").unwrap();
+        assert_eq!(
+            keymap
+                .handle(&press(Code::KeyS, "ы", Modifiers::CONTROL))
+                .as_deref(),
+            Some("save")
+        );
+    }
+}
@@ -0,0 +1,51 @@
+// Copyright 2018 The Druid Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `druid-shell` integrates with platform APIs to open windows and handle
+//! input, and provides a consistent interface to the application.
+
+#![warn(rust_2018_idioms)]
+
+pub use kurbo;
+pub use piet_common as piet;
+
+// Reexport the `keyboard_types` crate so that consumers can match on the same
+// enums we translate platform events into.
+pub use keyboard_types;
+
+#[macro_use]
+mod common_util;
+
+mod platform;
+
+pub mod application;
+pub mod clipboard;
+pub mod dialog;
+pub mod error;
+pub mod gamepad;
+pub mod hotkey;
+pub mod keyboard;
+pub mod keymap;
+pub mod menu;
+pub mod mouse;
+pub mod region;
+pub mod scale;
+pub mod screen;
+pub mod text;
+pub mod window;
+
+// The backend that `druid-shell` is built against for the current target. The
+// rest of the crate refers to it through this alias so platform-specific types
+// can be re-exported from the platform-independent modules.
+pub(crate) use platform::gtk as backend;
@@ -0,0 +1,170 @@
+// Copyright 2019 The Druid Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Mouse types.
+
+use crate::kurbo::{Point, Vec2};
+use crate::piet::ImageBuf;
+
+use crate::keyboard::Modifiers;
+
+pub use crate::backend::window::CustomCursor;
+
+/// Information about the mouse event.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MouseEvent {
+    /// The location of the mouse in the current window, in display points.
+    pub pos: Point,
+    /// Mouse buttons being held down during a move or after a click event.
+    /// Thus it will contain the `button` that triggered a mouse-down event,
+    /// and it will not contain the `button` that triggered a mouse-up event.
+    pub buttons: MouseButtons,
+    /// Keyboard modifiers at the time of the event.
+    pub mods: Modifiers,
+    /// The number of mouse clicks associated with this event. This will always
+    /// be `0` for a mouse-up and mouse-move events.
+    pub count: u8,
+    /// Focus is `true` on macOS when the mouse-down event (or its companion
+    /// mouse-up event) with `MouseButton::Left` was the event that caused the
+    /// window to gain focus.
+    pub focus: bool,
+    /// The button that was pressed down in the case of mouse-down, or the
+    /// button that was released in the case of mouse-up. This will always be
+    /// `MouseButton::None` in the case of mouse-move.
+    pub button: MouseButton,
+    /// The wheel movement, if this is a wheel event.
+    pub wheel_delta: Vec2,
+}
+
+/// An indicator of which mouse button was pressed.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum MouseButton {
+    /// No mouse button.
+    None,
+    /// Left mouse button.
+    Left,
+    /// Right mouse button.
+    Right,
+    /// Middle mouse button.
+    Middle,
+    /// First X button.
+    X1,
+    /// Second X button.
+    X2,
+}
+
+/// A set of [`MouseButton`]s.
+#[derive(PartialEq, Eq, Clone, Copy, Default)]
+pub struct MouseButtons(u8);
+
+impl MouseButtons {
+    /// Create a new empty set.
+    pub fn new() -> MouseButtons {
+        MouseButtons(0)
+    }
+
+    /// Add the `button` to the set.
+    pub fn insert(&mut self, button: MouseButton) {
+        self.0 |= 1 << (button as u8);
+    }
+
+    /// Remove the `button` from the set.
+    pub fn remove(&mut self, button: MouseButton) {
+        self.0 &= !(1 << (button as u8));
+    }
+
+    /// Whether the `button` is in the set.
+    pub fn contains(self, button: MouseButton) -> bool {
+        (self.0 & (1 << (button as u8))) != 0
+    }
+
+    /// Whether the set is empty.
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl std::fmt::Debug for MouseButtons {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "MouseButtons({:05b})", self.0 >> 1)
+    }
+}
+
+/// Mouse cursors.
+#[derive(Clone, PartialEq)]
+pub enum Cursor {
+    /// The default arrow cursor.
+    Arrow,
+    /// A vertical I-beam, for indicating text editing.
+    IBeam,
+    /// A crosshair, for precise selection.
+    Crosshair,
+    /// An open hand, for indicating draggable content.
+    OpenHand,
+    /// Indicates an action that is not permitted.
+    NotAllowed,
+    /// A bidirectional horizontal resize cursor.
+    ResizeLeftRight,
+    /// A bidirectional vertical resize cursor.
+    ResizeUpDown,
+    /// A horizontal resize cursor for table columns.
+    ResizeColumn,
+    /// A vertical resize cursor for table rows.
+    ResizeRow,
+    /// A diagonal resize cursor (top-left / bottom-right).
+    ResizeNwSe,
+    /// A diagonal resize cursor (top-right / bottom-left).
+    ResizeNeSw,
+    /// The pointing-hand cursor, as used over links.
+    Pointer,
+    /// Indicates that something can be scrolled in any direction.
+    AllScroll,
+    /// Indicates that something can be moved.
+    Move,
+    /// Indicates a busy state where interaction is blocked.
+    Wait,
+    /// Indicates a busy state where interaction is still possible.
+    Progress,
+    /// Indicates that help is available.
+    Help,
+    /// Indicates a table cell or set of cells can be selected.
+    Cell,
+    /// Indicates that something can be zoomed in.
+    ZoomIn,
+    /// Indicates that something can be zoomed out.
+    ZoomOut,
+    /// A custom cursor, built from an image with [`WindowHandle::make_cursor`].
+    ///
+    /// [`WindowHandle::make_cursor`]: crate::window::WindowHandle::make_cursor
+    Custom(CustomCursor),
+}
+
+/// A description of a cursor to be created from an image.
+#[derive(Clone)]
+pub struct CursorDesc {
+    /// The image the cursor is drawn from.
+    pub image: ImageBuf,
+    /// The hotspot, in the image's coordinate space, that tracks the pointer.
+    pub hot: Point,
+}
+
+impl CursorDesc {
+    /// Create a new `CursorDesc` from an image and a hotspot.
+    pub fn new(image: ImageBuf, hot: impl Into<Point>) -> CursorDesc {
+        CursorDesc {
+            image,
+            hot: hot.into(),
+        }
+    }
+}
@@ -15,8 +15,11 @@
 //! Interactions with the system pasteboard on GTK+.
 
 use gdk::{ContentProvider,ContentProviderExt, Display,ContentFormats};
-use gtk::glib::value::Value;
+use gdk::gdk_pixbuf::{Colorspace, Pixbuf};
+use gdk::prelude::TextureExt;
+use gtk::glib::value::{ToValue, Value};
 use gtk::glib::types::Type;
+use gtk::glib::StaticType;
 use gtk::glib::Bytes;
 use gtk::glib::GString;
 use gtk::glib::source::PRIORITY_HIGH;
@@ -24,30 +27,86 @@ use gtk::glib::Error;
 use gtk::gio::prelude::InputStreamExt;
 use gtk::gio::InputStream;
 use gtk::gio::NONE_CANCELLABLE;
-use std::sync::mpsc::{Sender, Receiver};
-use std::sync::mpsc;
+use gtk::glib::MainContext;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
 
 use crate::clipboard::{ClipboardFormat, FormatId};
 
 use core::convert::AsRef;
 
-/// The system clipboard.
+/// Which of the system selections a [`Clipboard`] operates on.
+///
+/// X11 and Wayland expose two independent selections: the regular `CLIPBOARD`,
+/// driven by explicit copy/paste, and the `PRIMARY` selection, populated by
+/// text highlighting and pasted with a middle click. On backends without a
+/// native primary selection, GDK emulates it within the process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Selection {
+    /// The regular `CLIPBOARD` selection.
+    Clipboard,
+    /// The `PRIMARY` (middle-click) selection.
+    Primary,
+}
+
+impl Default for Selection {
+    fn default() -> Self {
+        Selection::Clipboard
+    }
+}
+
+/// Raw un-premultiplied RGBA image data, as exchanged through the clipboard.
+///
+/// This mirrors the `ImageData` abstraction used by cross-platform clipboard
+/// crates: a tightly packed `width * height * 4` byte buffer with one byte per
+/// channel, rows laid out top to bottom.
 #[derive(Debug, Clone)]
-pub struct Clipboard;
+pub struct ImageData {
+    /// The image width in pixels.
+    pub width: usize,
+    /// The image height in pixels.
+    pub height: usize,
+    /// Un-premultiplied RGBA pixels, `width * height * 4` bytes.
+    pub bytes: Vec<u8>,
+}
+
+/// The system clipboard.
+#[derive(Debug, Clone, Default)]
+pub struct Clipboard {
+    selection: Selection,
+}
 
 impl Clipboard {
+    /// A clipboard backed by the `PRIMARY` (middle-click) selection.
+    pub fn primary() -> Clipboard {
+        Clipboard {
+            selection: Selection::Primary,
+        }
+    }
+
+    /// The GDK clipboard for this selection.
+    ///
+    /// On backends without a native primary selection, GDK's
+    /// `get_primary_clipboard` is a local, in-process clipboard; it is not
+    /// shared with other applications or with the regular clipboard.
+    fn gdk_clipboard(&self) -> gdk::Clipboard {
+        let display = Display::get_default().unwrap();
+        match self.selection {
+            Selection::Clipboard => display.get_clipboard(),
+            Selection::Primary => display.get_primary_clipboard(),
+        }
+    }
+
     /// Put a string onto the system clipboard.
     pub fn put_string(&mut self, s: impl AsRef<str>) {
-        let display = Display::get_default().unwrap();
-        let clipboard = display.get_clipboard();
+        let clipboard = self.gdk_clipboard();
 
         clipboard.set_text(s.as_ref())
     }
 
     /// Put multi-format data on the system clipboard.
     pub fn put_formats(&mut self, formats: &[ClipboardFormat]) {
-        let display = Display::get_default().unwrap();
-        let clipboard = display.get_clipboard();
+        let clipboard = self.gdk_clipboard();
 
         let mut providers = Vec::<ContentProvider>::new();
         for format in formats{
@@ -61,10 +120,75 @@ impl Clipboard {
         }
     }
 
+    /// Put an RGBA image onto the system clipboard.
+    pub fn put_image(&mut self, img: ImageData) {
+        let clipboard = self.gdk_clipboard();
+
+        let stride = (img.width * 4) as i32;
+        let pixbuf = Pixbuf::from_mut_slice(
+            img.bytes,
+            Colorspace::Rgb,
+            // has_alpha
+            true,
+            // bits_per_sample
+            8,
+            img.width as i32,
+            img.height as i32,
+            stride,
+        );
+        let texture = gdk::Texture::new_for_pixbuf(&pixbuf);
+        let provider = ContentProvider::new_for_value(&texture.to_value());
+        if !clipboard.set_content(Some(&provider)) {
+            tracing::warn!("failed to set clipboard image.");
+        }
+    }
+
+    /// Get an RGBA image from the system clipboard, if one is available.
+    ///
+    /// The image is read from the clipboard's local content provider as a
+    /// `gdk::Texture` and returned as un-premultiplied RGBA. Only content
+    /// this process put on the clipboard is seen; image data advertised by
+    /// another application (`image/png`, etc.) is not read, and `None` is
+    /// returned for it.
+    pub fn get_image(&self) -> Option<ImageData> {
+        let clipboard = self.gdk_clipboard();
+        let provider = clipboard.get_content()?;
+
+        let mut value = Value::from_type(gdk::Texture::static_type());
+        provider.get_value(&mut value).ok()?;
+        let texture = value.get::<gdk::Texture>().ok()?;
+
+        let width = texture.get_width() as usize;
+        let height = texture.get_height() as usize;
+        let mut bytes = vec![0u8; width * height * 4];
+        texture.download(&mut bytes, width * 4);
+        // `download` hands back `GDK_MEMORY_DEFAULT` data: premultiplied,
+        // B8G8R8A8. Swizzle to RGBA and divide the alpha back out so the result
+        // is the straight-alpha RGBA we document and that `put_image` expects.
+        for px in bytes.chunks_exact_mut(4) {
+            let (b, g, r, a) = (px[0], px[1], px[2], px[3]);
+            let straight = |c: u8| {
+                if a == 0 {
+                    0
+                } else {
+                    ((c as u32 * 255 + a as u32 / 2) / a as u32).min(255) as u8
+                }
+            };
+            px[0] = straight(r);
+            px[1] = straight(g);
+            px[2] = straight(b);
+            px[3] = a;
+        }
+        Some(ImageData {
+            width,
+            height,
+            bytes,
+        })
+    }
+
     /// Get a string from the system clipboard, if one is available.
     pub fn get_string(&self) -> Option<String> {
-        let display = Display::get_default().unwrap();
-        let clipboard = display.get_clipboard();
+        let clipboard = self.gdk_clipboard();
         let provider = clipboard.get_content()?;
 
         let mut value = Value::from_type(Type::String);
@@ -79,47 +203,236 @@ impl Clipboard {
 
     /// Given a list of supported clipboard types, returns the supported type which has
     /// highest priority on the system clipboard, or `None` if no types are supported.
+    ///
+    /// A requested type counts as supported if the source app advertised it
+    /// directly *or* if we can synthesize it from an advertised type through a
+    /// registered [`Conversion`], so paste behaves consistently regardless of
+    /// which format the source actually put down.
     pub fn preferred_format(&self, formats: &[FormatId]) -> Option<FormatId> {
-        let display = gdk::Display::get_default().unwrap();
-        let clipboard = display.get_clipboard();
+        let clipboard = self.gdk_clipboard();
         let targets = clipboard.get_formats()?;
         for format in formats {
-            if targets.contain_mime_type(format){
-                return Some(format)
+            if targets.contain_mime_type(format) {
+                return Some(format);
+            }
+            if CONVERSIONS
+                .iter()
+                .any(|c| c.to == *format && targets.contain_mime_type(c.from))
+            {
+                return Some(format);
             }
         }
         None
     }
 
+    /// Return data in a given format, delivering it through `cb` without
+    /// blocking the GTK main loop.
+    ///
+    /// GDK clipboard reads complete by dispatching on the main loop, so the
+    /// bytes arrive in `cb` from a later iteration of that loop rather than
+    /// being returned inline. This is the recommended entry point for reading
+    /// the clipboard; the synchronous [`Clipboard::get_format`] is built on top
+    /// of it by spinning a nested main context.
+    pub fn get_format_async(&self, format: FormatId, cb: impl FnOnce(Option<Vec<u8>>) + 'static) {
+        let clipboard = self.gdk_clipboard();
+        clipboard.read_async(
+            &[format],
+            PRIORITY_HIGH,
+            NONE_CANCELLABLE,
+            move |clip_data: Result<(InputStream, GString), Error>| {
+                cb(clip_data.ok().and_then(|(stream, _)| read_stream(&stream)));
+            },
+        );
+    }
+
+    /// Get a string from the system clipboard asynchronously, delivering it
+    /// through `cb` without blocking the GTK main loop.
+    pub fn get_string_async(&self, cb: impl FnOnce(Option<String>) + 'static) {
+        self.get_format_async("text/plain;charset=utf-8", move |bytes| {
+            cb(bytes.and_then(|b| String::from_utf8(b).ok()));
+        });
+    }
+
     /// Return data in a given format, if available.
     ///
     /// It is recommended that the `fmt` argument be a format returned by
-    /// [`Clipboard::preferred_format`]
+    /// [`Clipboard::preferred_format`]. This blocks the calling thread; because
+    /// the read completes on the GTK main loop, we spin a nested
+    /// [`glib::MainContext`] iteration until the callback fires rather than
+    /// blocking on a channel, which would deadlock the loop.
     pub fn get_format(&self, format: FormatId) -> Option<Vec<u8>> {
-        //TODO: COMPLETELY UNTESTED PLS TEST
-        let display = Display::get_default().unwrap();
-        let clipboard = display.get_clipboard();
-
-        let (tx, rx): (Sender<Option::<Vec<u8>>>, Receiver<Option::<Vec<u8>>>) = mpsc::channel();
-        clipboard.read_async(&[format],PRIORITY_HIGH, NONE_CANCELLABLE, move |clip_data: Result<(InputStream, GString), Error>|{
-            if clip_data.is_ok(){
-                let bytes = (clip_data.ok().unwrap()).0.read_bytes(usize::MAX, NONE_CANCELLABLE);
-                if bytes.is_ok(){
-                    tx.send(Some(Vec::from(AsRef::<[u8]>::as_ref(&bytes.unwrap())))).unwrap();
-                }else{
-                    tx.send(None).unwrap();
-                }
-            }else{
-                tx.send(None).unwrap();
+        // If the format isn't advertised directly, try to synthesize it from a
+        // related type the source app did put down.
+        let advertised = self
+            .gdk_clipboard()
+            .get_formats()
+            .map(|t| t.contain_mime_type(format))
+            .unwrap_or(false);
+        if !advertised {
+            // Pick a conversion whose *source* is actually on the clipboard, not
+            // merely the first whose target matches: several conversions can
+            // share a target MIME, and choosing one whose `from` isn't
+            // advertised would fail even when another viable path exists. This
+            // keeps `get_format` in agreement with `preferred_format`.
+            let targets = self.gdk_clipboard().get_formats();
+            let conv = CONVERSIONS.iter().find(|c| {
+                c.to == format
+                    && targets
+                        .as_ref()
+                        .map_or(false, |t| t.contain_mime_type(c.from))
+            });
+            if let Some(conv) = conv {
+                let source = self.get_format_raw(conv.from)?;
+                return (conv.convert)(&source);
+            }
+        }
+        self.get_format_raw(format)
+    }
+
+    /// Read the raw bytes advertised under `format`, with no synthesis.
+    fn get_format_raw(&self, format: FormatId) -> Option<Vec<u8>> {
+        let result = Rc::new(RefCell::new(None));
+        let done = Rc::new(Cell::new(false));
+        self.get_format_async(format, {
+            let result = result.clone();
+            let done = done.clone();
+            move |bytes| {
+                *result.borrow_mut() = bytes;
+                done.set(true);
             }
         });
-        rx.recv().unwrap()
+
+        let context = MainContext::default();
+        while !done.get() {
+            context.iteration(true);
+        }
+        result.borrow_mut().take()
     }
 
     pub fn available_type_names(&self) -> Vec<String> {
-        let display = gdk::Display::get_default().unwrap();
-        let clipboard = display.get_clipboard();
+        let clipboard = self.gdk_clipboard();
         let formats = clipboard.get_formats().unwrap_or_else(||ContentFormats::new(&[]));
         formats.get_mime_types().0.iter().map(|s|String::from(s.as_str())).collect()
     }
 }
+
+/// A synthetic conversion from one clipboard MIME type to another.
+///
+/// Inspired by the clipboard "synthesizers" in FreeRDP's winpr: when a widget
+/// asks for a type the source app didn't advertise but that we can derive from
+/// one it did, we run the matching converter on the source bytes.
+struct Conversion {
+    from: FormatId,
+    to: FormatId,
+    convert: fn(&[u8]) -> Option<Vec<u8>>,
+}
+
+/// The registered clipboard conversions, consulted by `preferred_format` and
+/// `get_format`.
+static CONVERSIONS: &[Conversion] = &[
+    Conversion {
+        from: "text/html",
+        to: "text/plain;charset=utf-8",
+        convert: html_to_plain,
+    },
+    Conversion {
+        from: "text/plain",
+        to: "text/plain;charset=utf-8",
+        convert: normalize_crlf,
+    },
+    Conversion {
+        from: "text/plain;charset=utf-8",
+        to: "text/html",
+        convert: plain_to_html,
+    },
+];
+
+/// Strip tags from an HTML body, decoding it as UTF-8 or, failing that, as
+/// UTF-16, to produce plain UTF-8 text.
+fn html_to_plain(bytes: &[u8]) -> Option<Vec<u8>> {
+    let html = decode_text(bytes)?;
+    let mut out = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for ch in html.chars() {
+        match ch {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            _ if !in_tag => out.push(ch),
+            _ => {}
+        }
+    }
+    Some(out.into_bytes())
+}
+
+/// Normalize a raw string to UTF-8 plain text with `\n` line endings, turning
+/// both CR/LF pairs and lone CRs into LF.
+fn normalize_crlf(bytes: &[u8]) -> Option<Vec<u8>> {
+    let text = decode_text(bytes)?;
+    Some(text.replace("\r\n", "\n").replace('\r', "\n").into_bytes())
+}
+
+/// Wrap plain UTF-8 text in a minimal HTML document.
+fn plain_to_html(bytes: &[u8]) -> Option<Vec<u8>> {
+    let text = decode_text(bytes)?;
+    let escaped = text
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+    Some(format!("<html><body>{}</body></html>", escaped).into_bytes())
+}
+
+/// Decode clipboard bytes as text, trying UTF-8 first and then UTF-16 (the
+/// encoding some apps use for `text/html`).
+fn decode_text(bytes: &[u8]) -> Option<String> {
+    if let Ok(s) = std::str::from_utf8(bytes) {
+        return Some(s.to_owned());
+    }
+    if bytes.len() % 2 == 0 {
+        let units: Vec<u16> = bytes
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect();
+        return String::from_utf16(&units).ok();
+    }
+    None
+}
+
+/// The size of a single `read_bytes` chunk when draining a clipboard stream.
+const READ_CHUNK: usize = 64 * 1024;
+
+/// Read an input stream to the end in fixed-size chunks.
+///
+/// We deliberately avoid `read_bytes(usize::MAX, ..)`: passing an unbounded
+/// length asks GDK to allocate the whole payload up front and misbehaves on
+/// large transfers, so we loop on [`READ_CHUNK`]-sized reads until the stream
+/// is exhausted instead.
+fn read_stream(stream: &InputStream) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    loop {
+        let bytes = stream.read_bytes(READ_CHUNK, NONE_CANCELLABLE).ok()?;
+        let chunk = AsRef::<[u8]>::as_ref(&bytes);
+        if chunk.is_empty() {
+            break;
+        }
+        out.extend_from_slice(chunk);
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gtk::gio::MemoryInputStream;
+    use gtk::glib::object::Cast;
+
+    #[test]
+    fn read_stream_reassembles_chunked_payload() {
+        // A payload several chunks long exercises the read loop; byte `i % 251`
+        // makes a mismatch at any offset obvious.
+        let payload: Vec<u8> = (0..READ_CHUNK * 2 + 37).map(|i| (i % 251) as u8).collect();
+        let stream: InputStream =
+            MemoryInputStream::from_bytes(&Bytes::from(&payload)).upcast();
+        let read = read_stream(&stream).expect("read should succeed");
+        assert_eq!(read, payload);
+    }
+}
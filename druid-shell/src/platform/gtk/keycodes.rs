@@ -14,6 +14,9 @@
 
 //! GTK code handling.
 
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+
 use gdk::keys::constants::*;
 use gdk::keys::Key as GDKKey;
 
@@ -21,6 +24,143 @@ use crate::keyboard_types::{Code,Key, Location};
 
 pub type RawKey = gdk::keys::Key;
 
+thread_local! {
+    // A hardware-scancode -> hardware-scancode remap table, consulted before we
+    // translate a raw code to a `Code`. It reflects OS-level remaps such as a
+    // Caps Lock <-> Ctrl swap, so `KeyEvent.code` matches what the user
+    // configured rather than the physical key. Lazily populated and cached;
+    // `refresh_remap_table` drops the cache so the next lookup reloads it.
+    // Nothing in this module calls it on a keyboard-layout change.
+    static SCANCODE_REMAP: RefCell<Option<HashMap<u16, u16>>> = RefCell::new(None);
+}
+
+/// Install an in-process scancode remap table (used on GTK/X11, where the OS
+/// does not expose the remap to us directly).
+pub fn install_remap_table(map: HashMap<u16, u16>) {
+    SCANCODE_REMAP.with(|cell| *cell.borrow_mut() = Some(map));
+}
+
+/// Invalidate the cached remap table so it is reloaded on next use, e.g. after
+/// a keyboard-layout-change notification.
+pub fn refresh_remap_table() {
+    SCANCODE_REMAP.with(|cell| *cell.borrow_mut() = None);
+}
+
+/// Apply the active scancode remap to a raw hardware code, loading the platform
+/// table on first use.
+pub fn remap_scancode(raw: u16) -> u16 {
+    SCANCODE_REMAP.with(|cell| {
+        let mut borrow = cell.borrow_mut();
+        let map = borrow.get_or_insert_with(load_platform_remap_table);
+        map.get(&raw).copied().unwrap_or(raw)
+    })
+}
+
+#[cfg(windows)]
+fn load_platform_remap_table() -> HashMap<u16, u16> {
+    // Parse `HKLM\SYSTEM\CurrentControlSet\Control\Keyboard Layout\Scancode Map`.
+    // The binary value is two u32 headers, a u32 entry count, then
+    // `count - 1` pairs of little-endian u16 scancodes (mapped-to, mapped-from)
+    // followed by a null terminator pair.
+    use winreg::enums::HKEY_LOCAL_MACHINE;
+    use winreg::RegKey;
+
+    let mut map = HashMap::new();
+    let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
+    let key = match hklm.open_subkey(r"SYSTEM\CurrentControlSet\Control\Keyboard Layout") {
+        Ok(key) => key,
+        Err(_) => return map,
+    };
+    let raw: Vec<u8> = match key.get_raw_value("Scancode Map") {
+        Ok(value) => value.bytes,
+        Err(_) => return map,
+    };
+    if raw.len() < 12 {
+        return map;
+    }
+    let count = u32::from_le_bytes([raw[8], raw[9], raw[10], raw[11]]) as usize;
+    for i in 0..count.saturating_sub(1) {
+        let off = 12 + i * 4;
+        if off + 4 > raw.len() {
+            break;
+        }
+        // Stored as (mapped-to, mapped-from); we key by the physical scancode.
+        let to = u16::from_le_bytes([raw[off], raw[off + 1]]);
+        let from = u16::from_le_bytes([raw[off + 2], raw[off + 3]]);
+        map.insert(from, to);
+    }
+    map
+}
+
+#[cfg(not(windows))]
+fn load_platform_remap_table() -> HashMap<u16, u16> {
+    // On GTK/X11 there is no OS-exposed table to read; callers install one
+    // explicitly with `install_remap_table`, so we start empty.
+    HashMap::new()
+}
+
+thread_local! {
+    // The hardware keycodes currently held down, so a key press that arrives
+    // while its code is already in the set can be flagged as an auto-repeat.
+    static PRESSED: RefCell<HashSet<u16>> = RefCell::new(HashSet::new());
+    // Interned key strings. Like tao's `insert_or_get_key_str`, each distinct
+    // character string is leaked exactly once so it can be handed out as
+    // `'static` without reallocating on every keystroke.
+    static INTERNED: RefCell<HashMap<String, &'static str>> = RefCell::new(HashMap::new());
+}
+
+/// Intern `s`, returning a `'static` view that is stable for the process
+/// lifetime. Repeated calls with the same string reuse the same allocation.
+pub fn intern(s: &str) -> &'static str {
+    INTERNED.with(|cell| {
+        if let Some(existing) = cell.borrow().get(s) {
+            return *existing;
+        }
+        let leaked: &'static str = Box::leak(s.to_owned().into_boxed_str());
+        cell.borrow_mut().insert(s.to_owned(), leaked);
+        leaked
+    })
+}
+
+/// Record that `hw_keycode` went down, returning `true` if it was already held
+/// (i.e. this press is an auto-repeat).
+pub fn note_key_pressed(hw_keycode: u16) -> bool {
+    PRESSED.with(|set| !set.borrow_mut().insert(hw_keycode))
+}
+
+/// Record that `hw_keycode` was released.
+pub fn note_key_released(hw_keycode: u16) {
+    PRESSED.with(|set| {
+        set.borrow_mut().remove(&hw_keycode);
+    });
+}
+
+/// Derive the *logical* key for a raw GDK key, respecting the active layout.
+///
+/// Named keys resolve through [`raw_key_to_key`]; anything it doesn't enumerate
+/// falls back to the Unicode value the keysym produces, so printable characters
+/// and dead-key results become `Key::Character` instead of being dropped.
+pub fn logical_key(raw: &RawKey) -> Key {
+    if let Some(key) = raw_key_to_key(raw) {
+        return key;
+    }
+    match composed_text(raw) {
+        Some(text) => Key::Character(intern(&text).to_owned()),
+        None => Key::Unidentified,
+    }
+}
+
+/// The composed text a keypress yields, if any. This may differ from the
+/// logical key under modifiers or IME, and is `None` for non-printable keys.
+pub fn composed_text(raw: &RawKey) -> Option<String> {
+    let c = raw.to_unicode()?;
+    if c >= ' ' && c != '\x7f' {
+        Some(c.to_string())
+    } else {
+        None
+    }
+}
+
 #[allow(clippy::just_underscores_and_digits, non_upper_case_globals)]
 pub fn raw_key_to_key(raw: &RawKey) -> Option<Key> {
     Some(match raw.clone() {
@@ -170,6 +310,125 @@ pub fn key_to_raw_key(src: &Key) -> Option<RawKey> {
 
 
 
+/// Map a raw hardware scancode to a [`Code`], honoring the active scancode
+/// remap so an OS-level swap such as Caps Lock <-> Ctrl is reflected in
+/// `KeyEvent.code`.
+///
+/// On X11/Wayland a hardware keycode is the evdev scancode plus 8, and that
+/// mapping is stable enough in practice to key a physical-position table off,
+/// the same way Mozilla's `NativeKeyToDOMCodeName.h` does. Scancodes we don't
+/// have a position for return [`Code::Unidentified`]; callers fall back to the
+/// keysym-based [`hardware_keycode_to_code`] for those.
+pub fn code_for_hardware_keycode(hw_keycode: u16) -> Code {
+    match remap_scancode(hw_keycode) {
+        9 => Code::Escape,
+        10 => Code::Digit1,
+        11 => Code::Digit2,
+        12 => Code::Digit3,
+        13 => Code::Digit4,
+        14 => Code::Digit5,
+        15 => Code::Digit6,
+        16 => Code::Digit7,
+        17 => Code::Digit8,
+        18 => Code::Digit9,
+        19 => Code::Digit0,
+        20 => Code::Minus,
+        21 => Code::Equal,
+        22 => Code::Backspace,
+        23 => Code::Tab,
+        24 => Code::KeyQ,
+        25 => Code::KeyW,
+        26 => Code::KeyE,
+        27 => Code::KeyR,
+        28 => Code::KeyT,
+        29 => Code::KeyY,
+        30 => Code::KeyU,
+        31 => Code::KeyI,
+        32 => Code::KeyO,
+        33 => Code::KeyP,
+        34 => Code::BracketLeft,
+        35 => Code::BracketRight,
+        36 => Code::Enter,
+        37 => Code::ControlLeft,
+        38 => Code::KeyA,
+        39 => Code::KeyS,
+        40 => Code::KeyD,
+        41 => Code::KeyF,
+        42 => Code::KeyG,
+        43 => Code::KeyH,
+        44 => Code::KeyJ,
+        45 => Code::KeyK,
+        46 => Code::KeyL,
+        47 => Code::Semicolon,
+        48 => Code::Quote,
+        49 => Code::Backquote,
+        50 => Code::ShiftLeft,
+        51 => Code::Backslash,
+        52 => Code::KeyZ,
+        53 => Code::KeyX,
+        54 => Code::KeyC,
+        55 => Code::KeyV,
+        56 => Code::KeyB,
+        57 => Code::KeyN,
+        58 => Code::KeyM,
+        59 => Code::Comma,
+        60 => Code::Period,
+        61 => Code::Slash,
+        62 => Code::ShiftRight,
+        63 => Code::NumpadMultiply,
+        64 => Code::AltLeft,
+        65 => Code::Space,
+        66 => Code::CapsLock,
+        67 => Code::F1,
+        68 => Code::F2,
+        69 => Code::F3,
+        70 => Code::F4,
+        71 => Code::F5,
+        72 => Code::F6,
+        73 => Code::F7,
+        74 => Code::F8,
+        75 => Code::F9,
+        76 => Code::F10,
+        77 => Code::NumLock,
+        78 => Code::ScrollLock,
+        79 => Code::Numpad7,
+        80 => Code::Numpad8,
+        81 => Code::Numpad9,
+        82 => Code::NumpadSubtract,
+        83 => Code::Numpad4,
+        84 => Code::Numpad5,
+        85 => Code::Numpad6,
+        86 => Code::NumpadAdd,
+        87 => Code::Numpad1,
+        88 => Code::Numpad2,
+        89 => Code::Numpad3,
+        90 => Code::Numpad0,
+        91 => Code::NumpadDecimal,
+        95 => Code::F11,
+        96 => Code::F12,
+        104 => Code::NumpadEnter,
+        105 => Code::ControlRight,
+        106 => Code::NumpadDivide,
+        107 => Code::PrintScreen,
+        108 => Code::AltRight,
+        110 => Code::Home,
+        111 => Code::ArrowUp,
+        112 => Code::PageUp,
+        113 => Code::ArrowLeft,
+        114 => Code::ArrowRight,
+        115 => Code::End,
+        116 => Code::ArrowDown,
+        117 => Code::PageDown,
+        118 => Code::Insert,
+        119 => Code::Delete,
+        127 => Code::Pause,
+        133 => Code::MetaLeft,
+        134 => Code::MetaRight,
+        135 => Code::ContextMenu,
+        _ => Code::Unidentified,
+    }
+}
+
 /// Map hardware keycode to code.
 ///
 /// In theory, the hardware keycode is device dependent, but in
@@ -14,17 +14,24 @@
 
 //! GTK implementation of menus.
 
+use std::collections::HashMap;
+
 use gdk::ModifierType;
-use gtk::{  WidgetExt};
-use gtk::{PopoverMenu,PopoverMenuBar};
-use gtk::ButtonExt;
-use gtk::gio::Menu as GIOMenu;
+use gtk::prelude::*;
+use gtk::{PopoverMenu, PopoverMenuBar};
+use gtk::gio::{ActionExt, ActionMapExt, Menu as GIOMenu, MenuItem as GIOMenuItem, SimpleAction, SimpleActionGroup};
+use gtk::glib::object::ObjectExt;
+use gtk::glib::{ToVariant, VariantTy};
 use super::keycodes;
 use super::window::WindowHandle;
-use crate::common_util::strip_access_key;
 use crate::hotkey::{HotKey, RawMods};
 use crate::keyboard::{KbKey, Modifiers};
 
+/// The name of the action group that menu commands are installed into on the
+/// window. Item actions are referenced from the model as `menu.menu_<id>`,
+/// and radio items as `menu.radio_<group>::<id>`.
+const ACTION_GROUP: &str = "menu";
+
 #[derive(Default, Debug, Clone)]
 pub struct Menu {
     items: Vec<MenuItem>,
@@ -37,11 +44,25 @@ enum MenuItem {
         id: u32,
         key: Option<HotKey>,
         enabled: bool,
+        kind: EntryKind,
     },
     SubMenu(String, Menu),
     Separator,
 }
 
+/// What flavour of entry this is, controlling the backing `gio` action and the
+/// check/bullet GTK renders for it.
+#[derive(Debug, Clone)]
+enum EntryKind {
+    /// A plain command item.
+    Normal,
+    /// A toggleable item, carrying its current checked state.
+    Checkbox(bool),
+    /// A member of a radio group identified by `group`, with `checked` marking
+    /// the active member.
+    Radio { group: u64, checked: bool },
+}
+
 impl Menu {
     pub fn new() -> Menu {
         Menu { items: Vec::new() }
@@ -54,7 +75,7 @@ impl Menu {
     pub fn add_dropdown(&mut self, menu: Menu, text: &str, _enabled: bool) {
         // TODO: implement enabled dropdown
         self.items
-            .push(MenuItem::SubMenu(strip_access_key(text), menu));
+            .push(MenuItem::SubMenu(gtk_mnemonic_label(text), menu));
     }
 
     pub fn add_item(
@@ -65,183 +86,316 @@ impl Menu {
         enabled: bool,
         _selected: bool,
     ) {
-        // TODO: implement selected items
+        let kind = if _selected {
+            EntryKind::Checkbox(true)
+        } else {
+            EntryKind::Normal
+        };
         self.items.push(MenuItem::Entry {
-            name: strip_access_key(text),
+            name: gtk_mnemonic_label(text),
             id,
             key: key.cloned(),
             enabled,
+            kind,
         });
     }
 
-    pub fn add_separator(&mut self) {
-        self.items.push(MenuItem::Separator)
+    /// Add a toggleable (checkbox) entry, initially `checked` or not.
+    pub fn add_check_item(
+        &mut self,
+        id: u32,
+        text: &str,
+        key: Option<&HotKey>,
+        enabled: bool,
+        checked: bool,
+    ) {
+        self.items.push(MenuItem::Entry {
+            name: gtk_mnemonic_label(text),
+            id,
+            key: key.cloned(),
+            enabled,
+            kind: EntryKind::Checkbox(checked),
+        });
     }
 
-    fn append_items_to_menu(
-        self,
-        menu: &mut PopoverMenuBar,
-        handle: &WindowHandle,
+    /// Add a radio entry belonging to `group`. At most one member of a group
+    /// shows its bullet at a time, driven by a single stateful action; none
+    /// does until a member is added or set as checked.
+    pub fn add_radio_item(
+        &mut self,
+        id: u32,
+        text: &str,
+        key: Option<&HotKey>,
+        enabled: bool,
+        group: u64,
+        checked: bool,
     ) {
-        let mut i = 0;
+        self.items.push(MenuItem::Entry {
+            name: gtk_mnemonic_label(text),
+            id,
+            key: key.cloned(),
+            enabled,
+            kind: EntryKind::Radio { group, checked },
+        });
+    }
 
-        for item in self.items {
+    /// Update the checked state of a checkbox or radio entry, so the menu
+    /// reflects application state the next time it is built.
+    pub fn set_checked(&mut self, id: u32, checked: bool) {
+        for item in &mut self.items {
             match item {
-                MenuItem::Entry {
-                    name,
-                    id,
-                    key,
-                    enabled,
-                } => {
-                    let item = gtk::Button::with_label(&name);
-                    item.set_sensitive(enabled);
-
-                    if let Some(k) = key {
-                        let controller = gtk::ShortcutController::new();
-                        controller.add_shortcut(&register_accelerator(&k));
-                        item.add_controller(&controller)
-                    }
-
-                    let handle = handle.clone();
-                    item.connect_activate(move |_| {
-                        if let Some(state) = handle.state.upgrade() {
-                            state.handler.borrow_mut().command(id);
-                        }
-                    });
-
-                    menu.add_child(&item,name.as_str());
-                }
-                MenuItem::SubMenu(name, submenu) => {
-                    let item = gtk::MenuButton::new();
-                    item.set_label(&name);
-                    item.set_popover(Some(&submenu.clone().into_gtk_menu(handle)));
-
-                    menu.add_child(&item,name.as_str());
-                }
-                MenuItem::Separator => {
-                    i+=1;
-                    menu.add_child(&gtk::Separator::new(gtk::Orientation::Horizontal),format!("sep{}",i).as_str());
+                MenuItem::Entry { id: entry_id, kind, .. } if *entry_id == id => match kind {
+                    EntryKind::Checkbox(state) => *state = checked,
+                    EntryKind::Radio { checked: state, .. } => *state = checked,
+                    EntryKind::Normal => {}
                 },
+                MenuItem::SubMenu(_, submenu) => submenu.set_checked(id, checked),
+                _ => {}
             }
         }
     }
-    fn append_items_to_menu_nonbar(
-        self,
-        menu: &mut PopoverMenu,
+
+    pub fn add_separator(&mut self) {
+        self.items.push(MenuItem::Separator)
+    }
+
+    /// Populate `menu` from this tree, creating a `SimpleAction` in `group` per
+    /// entry (one shared action per radio group) and wiring it to
+    /// `command(id)`. This is the single
+    /// source of truth for what the `PopoverMenu(Bar)` renders: the model
+    /// carries the labels and the detailed action names, the action group
+    /// carries the behaviour, and accelerators are registered on the
+    /// application so the `HotKey` fires from anywhere in the window.
+    fn append_items_to_giomenu(
+        &self,
+        menu: &GIOMenu,
+        group: &SimpleActionGroup,
+        radios: &mut HashMap<u64, SimpleAction>,
         handle: &WindowHandle,
     ) {
-        let mut i = 0;
-
-        for item in self.items {
+        for item in &self.items {
             match item {
                 MenuItem::Entry {
                     name,
                     id,
                     key,
                     enabled,
+                    kind,
                 } => {
-                    let item = gtk::Button::with_label(&name);
-                    item.set_sensitive(enabled);
+                    let id = *id;
+                    let (action, detailed) = match kind {
+                        EntryKind::Normal => {
+                            let action = SimpleAction::new(&format!("menu_{}", id), None);
+                            let activate_handle = handle.clone();
+                            action.connect_activate(move |_, _| {
+                                dispatch(&activate_handle, id);
+                            });
+                            group.add_action(&action);
+                            (action, format!("{}.menu_{}", ACTION_GROUP, id))
+                        }
+                        EntryKind::Checkbox(checked) => {
+                            let action = SimpleAction::new_stateful(
+                                &format!("menu_{}", id),
+                                None,
+                                &checked.to_variant(),
+                            );
+                            let activate_handle = handle.clone();
+                            // Activating a boolean stateful action makes GTK
+                            // request the negated state via `change-state`; we
+                            // commit it there and mirror it to the druid layer.
+                            // Toggling again in `activate` would cancel it out.
+                            action.connect_change_state(move |action, value| {
+                                if let Some(value) = value {
+                                    action.set_state(value);
+                                }
+                                dispatch(&activate_handle, id);
+                            });
+                            group.add_action(&action);
+                            (action, format!("{}.menu_{}", ACTION_GROUP, id))
+                        }
+                        EntryKind::Radio { group: gid, checked } => {
+                            let value = id.to_string();
+                            let action = radios.entry(*gid).or_insert_with(|| {
+                                let action = SimpleAction::new_stateful(
+                                    &format!("radio_{}", gid),
+                                    Some(VariantTy::STRING),
+                                    &"".to_variant(),
+                                );
+                                let activate_handle = handle.clone();
+                                action.connect_activate(move |action, param| {
+                                    if let Some(param) = param {
+                                        action.set_state(param);
+                                        if let Some(value) = param.get::<String>() {
+                                            if let Ok(target) = value.parse::<u32>() {
+                                                dispatch(&activate_handle, target);
+                                            }
+                                        }
+                                    }
+                                });
+                                group.add_action(&action);
+                                action
+                            });
+                            if *checked {
+                                action.set_state(&value.to_variant());
+                            }
+                            let detailed = format!("{}.radio_{}::{}", ACTION_GROUP, gid, value);
+                            (action.clone(), detailed)
+                        }
+                    };
+                    action.set_enabled(*enabled);
+
+                    let gio_item = GIOMenuItem::new(Some(name.as_str()), Some(detailed.as_str()));
+                    menu.append_item(&gio_item);
 
                     if let Some(k) = key {
-                        let controller = gtk::ShortcutController::new();
-                        controller.add_shortcut(&register_accelerator(&k));
-                        item.add_controller(&controller)
+                        register_accelerator(handle, &detailed, k);
                     }
-
-                    let handle = handle.clone();
-                    item.connect_activate(move |_| {
-                        if let Some(state) = handle.state.upgrade() {
-                            state.handler.borrow_mut().command(id);
-                        }
-                    });
-
-                    menu.add_child(&item,name.as_str());
                 }
                 MenuItem::SubMenu(name, submenu) => {
-                    let item = gtk::MenuButton::new();
-                    item.set_label(&name);
-                    item.set_popover(Some(&submenu.clone().into_gtk_menu(handle)));
-
-                    menu.add_child(&item,name.as_str());
+                    let item = GIOMenu::new();
+                    submenu.append_items_to_giomenu(&item, group, radios, handle);
+                    menu.append_submenu(Some(name.as_str()), &item);
                 }
                 MenuItem::Separator => {
-                    i+=1;
-                    menu.add_child(&gtk::Separator::new(gtk::Orientation::Horizontal),format!("sep{}",i).as_str());
-                },
+                    // GMenu renders a section break as a separator; an empty
+                    // section gives us that without a dead entry.
+                    let section = GIOMenu::new();
+                    menu.append_section(None, &section);
+                }
             }
         }
     }
 
-    fn append_items_to_giomenu(
-        self,
-        menu: &mut GIOMenu,
-    ) {
-        let mut i = 0;
-        for item in &self.items {
-            match item {
-                MenuItem::Entry {
-                    name,
-                    id,
-                    key,
-                    enabled,
-                } => {
-                    menu.append(Some(name.as_str()), None);
-                }
-                MenuItem::SubMenu(name, submenu) => {
-                    let mut item = GIOMenu::new();
-                    Some(&submenu.clone().append_items_to_giomenu(&mut item));
-                    menu.append_submenu(Some(name.as_str()),&item);
-                }
-                MenuItem::Separator => {
-                    i+=1;
-                    menu.append(Some(format!("sep{}",i).as_str()), None)
-                },
-            }
+    /// Build the model and action group, installing the group on the window so
+    /// the detailed action names in the model resolve. The group is returned
+    /// too so a caller exporting over DBus keeps it alive.
+    fn build_model(&self, handle: &WindowHandle) -> (GIOMenu, SimpleActionGroup) {
+        let gio_menu = GIOMenu::new();
+        let group = SimpleActionGroup::new();
+        let mut radios = HashMap::new();
+        self.append_items_to_giomenu(&gio_menu, &group, &mut radios, handle);
+        if let Some(state) = handle.state.upgrade() {
+            state.window.insert_action_group(ACTION_GROUP, Some(&group));
         }
+        (gio_menu, group)
     }
 
-    pub(crate) fn into_gtk_menubar(
-        self,
-        handle: &WindowHandle,
-    ) -> PopoverMenuBar {
-        let mut gio_menu = GIOMenu::new();
-        self.clone().append_items_to_giomenu(&mut gio_menu);
+    /// Build the menubar. Returns `None` when a global menu host is present (a
+    /// GNOME/Unity app-menu panel), in which case the model is exported over
+    /// DBus instead of embedded as a widget, and the caller should not add a
+    /// `PopoverMenuBar` to the window.
+    pub(crate) fn into_gtk_menubar(self, handle: &WindowHandle) -> Option<PopoverMenuBar> {
+        let (model, _group) = self.build_model(handle);
+        if global_menu_wanted(handle) {
+            export_global_menu(&model, handle);
+            None
+        } else {
+            Some(PopoverMenuBar::from_model(Some(&model)))
+        }
+    }
 
-        let mut menu = PopoverMenuBar::from_model(Some(&gio_menu));
+    pub fn into_gtk_menu(self, handle: &WindowHandle) -> PopoverMenu {
+        let (model, _group) = self.build_model(handle);
+        PopoverMenu::from_model(Some(&model))
+    }
+}
 
-        self.append_items_to_menu(&mut menu, handle);
+/// Whether the running desktop shell draws the menubar itself (exported over
+/// DBus) rather than expecting the application to embed it. This is the same
+/// `gtk-shell-shows-menubar` setting GTK consults internally.
+fn global_menu_wanted(handle: &WindowHandle) -> bool {
+    if let Some(state) = handle.state.upgrade() {
+        let mut value = gtk::glib::value::Value::from_type(gtk::glib::types::Type::BOOL);
+        if state
+            .window
+            .get_display()
+            .get_setting("gtk-shell-shows-menubar", &mut value)
+        {
+            return value
+                .downcast::<bool>()
+                .map(|v| v.get_some())
+                .unwrap_or(false);
+        }
+    }
+    false
+}
 
-        menu
+/// Hand the menubar model to the `GtkApplication`, which exports it and its
+/// action group over the application's `gio::DBusConnection` (the `org.gtk.Menus`
+/// interface) and sets the `_GTK_MENUBAR_OBJECT_PATH`/`_GTK_APPLICATION_OBJECT_PATH`
+/// properties on the window so global-menu panels can render it.
+fn export_global_menu(model: &GIOMenu, handle: &WindowHandle) {
+    if let Some(state) = handle.state.upgrade() {
+        if let Some(app) = state.window.get_application() {
+            app.set_menubar(Some(model));
+            state.window.set_show_menubar(true);
+        }
     }
+}
 
-    pub fn into_gtk_menu(self, handle: &WindowHandle) -> PopoverMenu {
-        let mut gio_menu = GIOMenu::new();
-        self.clone().append_items_to_giomenu(&mut gio_menu);
-        let mut menu = PopoverMenu::from_model(Some(&gio_menu));
+/// Translate druid's access-key marker into GTK's underline convention so the
+/// mnemonic letter is underlined and reachable with Alt. druid marks the
+/// mnemonic with `&` (and `&&` for a literal `&`); GTK uses a leading `_` and
+/// `__` for a literal underscore, so existing underscores are escaped.
+fn gtk_mnemonic_label(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '&' => {
+                if chars.peek() == Some(&'&') {
+                    chars.next();
+                    out.push('&');
+                } else {
+                    out.push('_');
+                }
+            }
+            '_' => out.push_str("__"),
+            other => out.push(other),
+        }
+    }
+    out
+}
 
-        self.append_items_to_menu_nonbar(&mut menu, handle);
+/// Route a menu activation to the window's handler as a `command`.
+fn dispatch(handle: &WindowHandle, id: u32) {
+    if let Some(state) = handle.state.upgrade() {
+        state.handler.borrow_mut().command(id);
+    }
+}
 
-        menu
+/// Register `hotkey` as the accelerator for `detailed_action` on the owning
+/// application, which is where GTK4 looks up global accelerators.
+fn register_accelerator(handle: &WindowHandle, detailed_action: &str, hotkey: &HotKey) {
+    let accel = match accel_string(hotkey) {
+        Some(accel) => accel,
+        None => return,
+    };
+    if let Some(state) = handle.state.upgrade() {
+        if let Some(app) = state.window.get_application() {
+            app.set_accels_for_action(detailed_action, &[accel.as_str()]);
+        }
     }
 }
 
-fn register_accelerator(menu_key: &HotKey) -> gtk::Shortcut {
-    let gdk_keyval = match &menu_key.key {
-        KbKey::Character(text) => text.chars().next().unwrap(),
+/// Render a [`HotKey`] as a GTK accelerator string, e.g. `<Control>s`.
+fn accel_string(menu_key: &HotKey) -> Option<String> {
+    let keyval = match &menu_key.key {
+        KbKey::Character(text) => text.chars().next()?,
         k => {
             if let Some(gdk_key) = keycodes::key_to_raw_key(k) {
-                gdk_key.to_unicode().unwrap()
+                gdk_key.to_unicode()?
             } else {
                 tracing::warn!("Cannot map key {:?}", k);
-                return gtk::Shortcut::new::<gtk::ShortcutTrigger,gtk::ActivateAction >(None, None);
+                return None;
             }
         }
     };
-    let trig = gtk::ShortcutTrigger::parse_string(format!("{}{}",modifiers_to_gdk_modifier_string(menu_key.mods),gdk_keyval).as_str()).unwrap();
-    let action = gtk::ActivateAction::get().unwrap();
-
-    gtk::Shortcut::new::<gtk::ShortcutTrigger,gtk::ActivateAction >(Some(&trig), Some(&action))
+    Some(format!(
+        "{}{}",
+        modifiers_to_gdk_modifier_string(menu_key.mods),
+        keyval
+    ))
 }
 
 fn modifiers_to_gdk_modifier_type(raw_modifiers: RawMods) -> gdk::ModifierType {
@@ -18,7 +18,19 @@ use crate::screen::Monitor;
 use gdk::Display;
 use kurbo::{Point, Rect, Size};
 use gtk::gio::{ListModelExt, ListModel};
-use gtk::glib::object::Cast;
+use gtk::glib::object::{Cast, ObjectExt};
+use std::cell::{Cell, RefCell};
+
+thread_local! {
+    // The enumerated monitor list, cached so repeated queries are cheap. It is
+    // invalidated (set back to `None`) whenever the display's `monitors` list
+    // model signals a change, so hotplugging a monitor is still reflected.
+    static MONITOR_CACHE: RefCell<Option<Vec<Monitor>>> = RefCell::new(None);
+    // Whether we have already connected the cache-invalidation handler to the
+    // display's monitor list. The signal outlives any single query, so it must
+    // be attached exactly once rather than on every cache miss.
+    static MONITOR_WATCH_REGISTERED: Cell<bool> = Cell::new(false);
+}
 
 fn translate_gdk_rectangle(r: gdk::Rectangle) -> Rect {
     Rect::from_origin_size(
@@ -27,35 +39,58 @@ fn translate_gdk_rectangle(r: gdk::Rectangle) -> Rect {
     )
 }
 
-fn translate_gdk_monitor(mon: gdk::Monitor, is_default: bool) -> Monitor {
-    let area = translate_gdk_rectangle(mon.get_geometry());
-    Monitor::new(
-        is_default,
-        area,
-        translate_gdk_rectangle(mon.get_geometry())
-    )
+pub(crate) fn translate_gdk_monitor(mon: gdk::Monitor, is_default: bool) -> Monitor {
+    // `get_geometry` is the full monitor rectangle; `get_workarea` excludes
+    // panels and docks, which is what layout code should place popups inside.
+    let geometry = translate_gdk_rectangle(mon.get_geometry());
+    let work_area = translate_gdk_rectangle(mon.get_workarea());
+    let mut monitor = Monitor::new(is_default, geometry, work_area);
+    monitor.set_scale_factor(mon.get_scale_factor() as f64);
+    // GDK reports the refresh rate in milli-hertz; convert to hertz.
+    monitor.set_refresh_rate(mon.get_refresh_rate() as f64 / 1000.0);
+    monitor
 }
 
-pub(crate) fn get_monitors() -> Vec<Monitor> {
-
-    let display = gdk::Display::get_default().unwrap();
-let defailt_monitors: &Vec<gdk::Monitor> = &display.get_monitors().map(|display: ListModel| {
-    (0..display.get_n_items())
-        .map(move |i| display.get_object(i).unwrap().downcast::<gdk::Monitor>().unwrap())
-}).unwrap().collect();
+/// Collect every monitor attached to `display`, marking the primary one.
+fn monitors_for_display(display: &Display) -> Vec<Monitor> {
+    let list: ListModel = match display.get_monitors() {
+        Some(list) => list,
+        None => return Vec::new(),
+    };
+    let primary = display.get_primary_monitor();
+    (0..list.get_n_items())
+        .filter_map(|i| list.get_object(i))
+        .filter_map(|obj| obj.downcast::<gdk::Monitor>().ok())
+        .map(|mon| {
+            let is_primary = primary.as_ref() == Some(&mon);
+            translate_gdk_monitor(mon, is_primary)
+        })
+        .collect()
+}
 
-    gdk::DisplayManager::get().unwrap()
-    .list_displays()
-    .iter()
-    .flat_map( |display: &Display| {
-        display.get_monitors()
-        .map(move |display: ListModel| {
-            (0..display.get_n_items())
-                .map(move |i| translate_gdk_monitor(display.get_object(i).unwrap().downcast::<gdk::Monitor>().unwrap(), defailt_monitors.contains(&display.get_object(i).unwrap().downcast::<gdk::Monitor>().unwrap())))
-        }).unwrap()
-        .collect::<Vec<Monitor>>()
-    }).collect::<Vec<Monitor>>()
+pub(crate) fn get_monitors() -> Vec<Monitor> {
+    if let Some(cached) = MONITOR_CACHE.with(|c| c.borrow().clone()) {
+        return cached;
+    }
 
+    let display = match Display::get_default() {
+        Some(display) => display,
+        None => return Vec::new(),
+    };
 
+    // Invalidate the cache whenever the monitor list changes, so a hotplug is
+    // picked up on the next query. Connect the handler only once; otherwise
+    // each cache miss would leak another live signal handler on the list.
+    if !MONITOR_WATCH_REGISTERED.with(Cell::get) {
+        if let Some(list) = display.get_monitors() {
+            list.connect_items_changed(|_, _, _, _| {
+                MONITOR_CACHE.with(|c| *c.borrow_mut() = None);
+            });
+            MONITOR_WATCH_REGISTERED.with(|r| r.set(true));
+        }
+    }
 
+    let monitors = monitors_for_display(&display);
+    MONITOR_CACHE.with(|c| *c.borrow_mut() = Some(monitors.clone()));
+    monitors
 }
@@ -30,8 +30,15 @@ use gtk::{ApplicationWindow, DrawingArea, PopoverExt,EventControllerExt};
 use gtk::cairo;
 use tracing::{error, warn};
 use gtk::gdk_pixbuf::{Pixbuf,Colorspace};
+use gtk::glib::StaticType;
 #[cfg(feature = "raw-win-handle")]
-use raw_window_handle::{HasRawWindowHandle, RawWindowHandle};
+use raw_window_handle::{
+    DisplayHandle, HandleError, HasDisplayHandle, HasWindowHandle, RawDisplayHandle,
+    RawWindowHandle, WaylandDisplayHandle, WaylandWindowHandle, WindowHandle as RawWindowHandleRef,
+    XlibDisplayHandle, XlibWindowHandle,
+};
+#[cfg(feature = "raw-win-handle")]
+use std::ptr::NonNull;
 
 use crate::kurbo::{Insets, Point, Rect, Size, Vec2};
 use crate::piet::{Piet, PietText, RenderContext};
@@ -95,12 +102,55 @@ pub struct WindowHandle {
     marker: std::marker::PhantomData<*const ()>,
 }
 
+// raw-window-handle 0.6 splits the window and display handles into separate
+// traits. Both are derived from the realized `GdkSurface`, whose native handle
+// stays valid for the lifetime of the upgraded `WindowState`.
 #[cfg(feature = "raw-win-handle")]
-unsafe impl HasRawWindowHandle for WindowHandle {
-    fn raw_window_handle(&self) -> RawWindowHandle {
-        error!("HasRawWindowHandle trait not implemented for gtk.");
-        // GTK is not a platform, and there's no empty generic handle. Pick XCB randomly as fallback.
-        RawWindowHandle::Xcb(XcbHandle::empty())
+impl HasWindowHandle for WindowHandle {
+    fn window_handle(&self) -> Result<RawWindowHandleRef<'_>, HandleError> {
+        let state = self.state.upgrade().ok_or(HandleError::Unavailable)?;
+        // The surface must be realized before we can query its native handle.
+        let native = state.window.get_native().ok_or(HandleError::Unavailable)?;
+        let surface = native.get_surface().ok_or(HandleError::Unavailable)?;
+
+        let raw = if let Ok(x11_surface) = surface.clone().downcast::<gdk::x11::X11Surface>() {
+            RawWindowHandle::Xlib(XlibWindowHandle::new(x11_surface.get_xid() as _))
+        } else if let Ok(wl_surface) = surface.downcast::<gdk::wayland::WaylandSurface>() {
+            let ptr = NonNull::new(wl_surface.get_wl_surface().as_ptr() as *mut _)
+                .ok_or(HandleError::Unavailable)?;
+            RawWindowHandle::Wayland(WaylandWindowHandle::new(ptr))
+        } else {
+            error!("Unknown GDK backend; cannot produce a raw window handle.");
+            return Err(HandleError::NotSupported);
+        };
+
+        // Safety: the surface outlives the borrowed handle because `state` keeps
+        // the `WindowState` (and thus the surface) alive for the call.
+        Ok(unsafe { RawWindowHandleRef::borrow_raw(raw) })
+    }
+}
+
+#[cfg(feature = "raw-win-handle")]
+impl HasDisplayHandle for WindowHandle {
+    fn display_handle(&self) -> Result<DisplayHandle<'_>, HandleError> {
+        let state = self.state.upgrade().ok_or(HandleError::Unavailable)?;
+        let display = state.window.get_display();
+
+        let raw = if let Ok(x11_display) = display.clone().downcast::<gdk::x11::X11Display>() {
+            let ptr = NonNull::new(x11_display.get_xdisplay() as *mut _);
+            RawDisplayHandle::Xlib(XlibDisplayHandle::new(ptr, 0))
+        } else if let Ok(wl_display) = display.downcast::<gdk::wayland::WaylandDisplay>() {
+            let ptr = NonNull::new(wl_display.get_wl_display().as_ptr() as *mut _)
+                .ok_or(HandleError::Unavailable)?;
+            RawDisplayHandle::Wayland(WaylandDisplayHandle::new(ptr))
+        } else {
+            error!("Unknown GDK backend; cannot produce a raw display handle.");
+            return Err(HandleError::NotSupported);
+        };
+
+        // Safety: the display outlives the borrowed handle for the duration of
+        // the call, as it is owned by the live `WindowState`.
+        Ok(unsafe { DisplayHandle::borrow_raw(raw) })
     }
 }
 
@@ -148,13 +198,25 @@ pub(crate) struct WindowState {
     scale: Cell<Scale>,
     area: Cell<ScaledArea>,
     is_transparent: Cell<bool>,
+    /// The size to restore to when leaving the maximized/fullscreen state, in
+    /// display points. Remembered so that un-maximizing returns to the size the
+    /// window was originally requested at rather than the maximized size.
+    restore_size: Cell<Size>,
     /// Used to determine whether to honor close requests from the system: we inhibit them unless
     /// this is true, and this gets set to true when our client requests a close.
     closing: Cell<bool>,
     key_event_controler: gtk::EventControllerKey,
     focus_event_controler: gtk::EventControllerFocus,
+    // The input-method context. Key presses are fed through it so dead keys and
+    // CJK/IME composition work; it drives the `composition_*` handler callbacks.
+    im_context: gtk::IMMulticontext,
+    // Whether an IME composition is currently in progress. Key events delivered
+    // while this is set carry `is_composing = true`.
+    composing: Cell<bool>,
     click_controller: gtk::GestureClick,
     motion_controller: gtk::EventControllerMotion,
+    scroll_controller: gtk::EventControllerScroll,
+    drop_target: gtk::DropTarget,
 
     drawing_area: DrawingArea,
     // A cairo surface for us to render to; we copy this to the drawing_area whenever necessary.
@@ -173,10 +235,30 @@ pub(crate) struct WindowState {
     // The invalid region, in display points.
     invalid: RefCell<Region>,
     pub(crate) handler: RefCell<Box<dyn WinHandler>>,
+    // A single reentrancy flag guarding handler access. While this is set we
+    // are inside a handler callback, so any further attempt to enter is a
+    // genuine reentrant call and is queued on `handler_queue` rather than
+    // panicking on a double borrow. Deferred and idle work that arrives
+    // meanwhile is queued too, and all of it is drained once the handler
+    // returns.
+    in_handler: Cell<bool>,
     idle_queue: Arc<Mutex<Vec<IdleKind>>>,
     current_keycode: Cell<Option<u32>>, //actually a v
+    // The physical code, modifiers and repeat flag of the most recent key press.
+    // The IM `commit` signal carries only the committed text, so when it passes
+    // an ordinary character straight through we recover the rest of the event
+    // from here rather than reporting it as an unidentified key.
+    last_key_down: Cell<Option<(keyboard_types::Code, Modifiers, bool)>>,
+    // The last pointer position we saw, in display points. Scroll events don't
+    // carry a position of their own, so we report the wheel at the cursor.
+    last_mouse_pos: Cell<Point>,
     click_counter: ClickCounter,
     deferred_queue: RefCell<Vec<DeferredOp>>,
+    // Handler calls that arrived while we were already inside the handler.
+    // Re-entering would be a double borrow, so instead of dropping the work we
+    // queue it here and drain it once the outer call returns, the same way idle
+    // work is deferred.
+    handler_queue: RefCell<Vec<Box<dyn FnOnce(&mut dyn WinHandler)>>>,
 }
 
 #[derive(Clone, PartialEq)]
@@ -281,19 +363,34 @@ impl WindowBuilder {
 
         let vbox = gtk::Box::new(gtk::Orientation::Vertical, 0);
         let key_event_controler = gtk::EventControllerKey::new();
+        let im_context = gtk::IMMulticontext::new();
+        // Route all key presses through the IM context first; it only forwards
+        // events it doesn't consume, so composed input is handled here and
+        // direct key presses still reach `connect_key_pressed`.
+        key_event_controler.set_im_context(&im_context);
         let focus_event_controler = gtk::EventControllerFocus::new();
         let click_controller = gtk::GestureClick::new();
         let motion_controller = gtk::EventControllerMotion::new();
+        let scroll_controller =
+            gtk::EventControllerScroll::new(gtk::EventControllerScrollFlags::BOTH_AXES);
+        // Accept dropped files (as a `gdk::FileList`) and UTF-8 text.
+        let drop_target = gtk::DropTarget::new(
+            gdk::FileList::static_type(),
+            gdk::DragAction::COPY,
+        );
+        drop_target.set_types(&[gdk::FileList::static_type(), <String as StaticType>::static_type()]);
         vbox.add_controller(&key_event_controler);
         vbox.add_controller(&focus_event_controler);
         vbox.add_controller(&click_controller);
         vbox.add_controller(&motion_controller);
+        vbox.add_controller(&scroll_controller);
 
         let drawing_area = gtk::DrawingArea::new();
         drawing_area.set_hexpand(true);
         drawing_area.set_hexpand_set(true);
         drawing_area.set_vexpand(true);
         drawing_area.set_vexpand_set(true);
+        drawing_area.add_controller(&drop_target);
 
         vbox.append(&drawing_area);
         vbox.set_hexpand(true);
@@ -302,25 +399,45 @@ impl WindowBuilder {
         vbox.set_vexpand_set(true);
                         window.set_child(Some(&vbox));
 
+        // On Wayland/GNOME, suppressing the server titlebar leaves the window
+        // with no controls at all, so we draw our own client-side decorations:
+        // a draggable title area plus minimize/maximize/close buttons. Build it
+        // now, while `window` is still in scope, but prepend it only after the
+        // menubar below so the titlebar ends up above the menu, not under it.
+        let csd_header = if !self.show_titlebar {
+            Some(build_client_side_decorations(&window, &self.title, self.resizable))
+        } else {
+            None
+        };
+
         let win_state = Arc::new(WindowState {
             window,
             scale: Cell::new(scale),
             area: Cell::new(area),
             is_transparent: Cell::new(self.transparent & can_transparent),
+            restore_size: Cell::new(self.size),
             closing: Cell::new(false),
             key_event_controler,
             focus_event_controler,
+            im_context,
+            composing: Cell::new(false),
             click_controller,
             drawing_area,
             motion_controller,
+            scroll_controller,
+            drop_target,
             surface: RefCell::new(None),
             surface_size: Cell::new((0, 0)),
             invalid: RefCell::new(Region::EMPTY),
             handler: RefCell::new(handler),
+            in_handler: Cell::new(false),
             idle_queue: Arc::new(Mutex::new(vec![])),
             current_keycode: Cell::new(None),
+            last_key_down: Cell::new(None),
+            last_mouse_pos: Cell::new(Point::ZERO),
             click_counter: ClickCounter::default(),
             deferred_queue: RefCell::new(Vec::new()),
+            handler_queue: RefCell::new(Vec::new()),
         });
 
         self.app
@@ -347,8 +464,17 @@ impl WindowBuilder {
         }
 
         if let Some(menu) = self.menu {
-            let menu = menu.into_gtk_menubar(&handle);
-            vbox.prepend(&menu);
+            // `None` means a global menu host took the exported model, so we
+            // don't embed a menubar widget of our own.
+            if let Some(menu) = menu.into_gtk_menubar(&handle) {
+                vbox.prepend(&menu);
+            }
+        }
+
+        // Prepend last so the client-side titlebar sits at the very top, above
+        // the menubar.
+        if let Some(header) = csd_header {
+            vbox.prepend(&header);
         }
 
         win_state.drawing_area.set_can_focus(true);
@@ -389,6 +515,20 @@ impl WindowBuilder {
             }),
         );
 
+        // Keep the tracked size current when the top-level is resized. The draw
+        // callback recomputes `area` and reports the new size to the handler, so
+        // queuing a draw is enough to keep `get_size` and the handler in sync.
+        win_state.window.connect_default_width_notify(clone!(handle => move |_| {
+            if let Some(state) = handle.state.upgrade() {
+                state.window.queue_draw();
+            }
+        }));
+        win_state.window.connect_default_height_notify(clone!(handle => move |_| {
+            if let Some(state) = handle.state.upgrade() {
+                state.window.queue_draw();
+            }
+        }));
+
         win_state.drawing_area.set_draw_func(clone!(handle => move |drawing_area, context, width, height| {
             if let Some(state) = handle.state.upgrade() {
                 let mut scale = state.scale.get();
@@ -553,8 +693,10 @@ impl WindowBuilder {
                 if let Some(state) = handle.state.upgrade() {
                     let scale = state.scale.get();
                     let motion_state = motion.get_current_event_state();
+                    let pos = Point::from((x,y)).to_dp(scale);
+                    state.last_mouse_pos.set(pos);
                     let mouse_event = MouseEvent {
-                        pos: Point::from((x,y)).to_dp(scale),
+                        pos,
                         buttons: get_mouse_buttons_from_modifiers(motion_state),
                         mods: get_modifiers(Some(motion_state)),
                         count: 0,
@@ -568,74 +710,120 @@ impl WindowBuilder {
             }),
         );
 
-        //TODO: viewport is needed for scrolling
-        // win_state
-        //     .drawing_area
-        //     .connect_scroll_event(clone!(handle => move |_widget, scroll| {
-        //         if let Some(state) = handle.state.upgrade() {
-        //             let scale = state.scale.get();
-        //             let mods = get_modifiers(scroll.get_state());
-
-        //             // The magic "120"s are from Microsoft's documentation for WM_MOUSEWHEEL.
-        //             // They claim that one "tick" on a scroll wheel should be 120 units.
-        //             let shift = mods.shift();
-        //             let wheel_delta = match scroll.get_direction() {
-        //                 ScrollDirection::Up if shift => Some(Vec2::new(-120.0, 0.0)),
-        //                 ScrollDirection::Up => Some(Vec2::new(0.0, -120.0)),
-        //                 ScrollDirection::Down if shift => Some(Vec2::new(120.0, 0.0)),
-        //                 ScrollDirection::Down => Some(Vec2::new(0.0, 120.0)),
-        //                 ScrollDirection::Left => Some(Vec2::new(-120.0, 0.0)),
-        //                 ScrollDirection::Right => Some(Vec2::new(120.0, 0.0)),
-        //                 ScrollDirection::Smooth => {
-        //                     //TODO: Look at how gtk's scroll containers implements it
-        //                     let (mut delta_x, mut delta_y) = scroll.get_delta();
-        //                     delta_x *= 120.;
-        //                     delta_y *= 120.;
-        //                     if shift {
-        //                         delta_x += delta_y;
-        //                         delta_y = 0.;
-        //                     }
-        //                     Some(Vec2::new(delta_x, delta_y))
-        //                 }
-        //                 e => {
-        //                     eprintln!(
-        //                         "Warning: the Druid widget got some whacky scroll direction {:?}",
-        //                         e
-        //                     );
-        //                     None
-        //                 }
-        //             };
-
-        //             if let Some(wheel_delta) = wheel_delta {
-        //                 let mouse_event = MouseEvent {
-        //                     pos: Point::from(scroll.get_position()).to_dp(scale),
-        //                     buttons: get_mouse_buttons_from_modifiers(scroll.get_state()),
-        //                     mods,
-        //                     count: 0,
-        //                     focus: false,
-        //                     button: MouseButton::None,
-        //                     wheel_delta
-        //                 };
-
-        //                 state.with_handler(|h| h.wheel(&mouse_event));
-        //             }
-        //         }
+        win_state.scroll_controller.connect_scroll(clone!(handle => move |scroll, dx, dy| {
+            if let Some(state) = handle.state.upgrade() {
+                let scale = state.scale.get();
+                let scroll_state = scroll.get_current_event_state();
+                let mods = get_modifiers(Some(scroll_state));
+                let shift = mods.shift();
+
+                let is_precise = scroll.get_unit() == gdk::ScrollUnit::Surface;
+                let (mut delta_x, mut delta_y) = wheel_delta(dx, dy, is_precise, scale);
+                // A vertical wheel with Shift held scrolls horizontally.
+                if shift {
+                    delta_x += delta_y;
+                    delta_y = 0.0;
+                }
 
-        //         Inhibit(true)
-        //     }));
+                let mouse_event = MouseEvent {
+                    pos: state.last_mouse_pos.get(),
+                    buttons: get_mouse_buttons_from_modifiers(scroll_state),
+                    mods,
+                    count: 0,
+                    focus: false,
+                    button: MouseButton::None,
+                    wheel_delta: Vec2::new(delta_x, delta_y),
+                };
+
+                state.with_handler(|h| h.wheel(&mouse_event));
+            }
+
+            Inhibit(true)
+        }));
+
+        win_state.drop_target.connect_enter(clone!(handle => move |_target, x, y| {
+            if let Some(state) = handle.state.upgrade() {
+                let pos = Point::new(x, y).to_dp(state.scale.get());
+                state.with_handler(|h| h.drag_enter(pos));
+            }
+            gdk::DragAction::COPY
+        }));
+
+        win_state.drop_target.connect_motion(clone!(handle => move |_target, x, y| {
+            if let Some(state) = handle.state.upgrade() {
+                let pos = Point::new(x, y).to_dp(state.scale.get());
+                state.with_handler(|h| h.drag_move(pos));
+            }
+            gdk::DragAction::COPY
+        }));
+
+        win_state.drop_target.connect_leave(clone!(handle => move |_target| {
+            if let Some(state) = handle.state.upgrade() {
+                state.with_handler(|h| h.drag_leave());
+            }
+        }));
+
+        win_state.drop_target.connect_drop(clone!(handle => move |_target, value, x, y| {
+            if let Some(state) = handle.state.upgrade() {
+                let pos = Point::new(x, y).to_dp(state.scale.get());
+                if let Ok(files) = value.get::<gdk::FileList>() {
+                    for file in files.get_files() {
+                        if let Some(path) = file.get_path() {
+                            let info = FileInfo { path };
+                            state.with_handler(|h| h.dropped_file(info, pos));
+                        }
+                    }
+                    return true;
+                }
+                if let Ok(text) = value.get::<String>() {
+                    state.with_handler(|h| h.dropped_text(&text, pos));
+                    return true;
+                }
+            }
+            false
+        }));
 
         win_state
             .key_event_controler
-            .connect_key_pressed(clone!(handle => move |_controler, key, _u32, modi| {
+            .connect_key_pressed(clone!(handle => move |controler, key, keycode, modi| {
                 if let Some(state) = handle.state.upgrade() {
 
-                    let repeat = state.current_keycode.get().clone() == Some(*key);
+                    // Repeat is determined by the set of currently-held hardware
+                    // keycodes: a press of an already-down code is auto-repeat.
+                    let repeat = keycodes::note_key_pressed(keycode as u16);
 
                     state.current_keycode.set(Some(*key));
 
-                    state.with_handler(|h|
-                        h.key_down(make_key_event(&key, repeat, KeyState::Down, Some(modi)))
-                    );
+                    // Stash the physical details of this press so the IM `commit`
+                    // passthrough can reconstruct a faithful `KeyEvent` for the
+                    // character it hands back.
+                    let code = match keycodes::code_for_hardware_keycode(keycode as u16) {
+                        keyboard_types::Code::Unidentified => keycodes::hardware_keycode_to_code(&key),
+                        code => code,
+                    };
+                    state
+                        .last_key_down
+                        .set(Some((code, get_modifiers(Some(modi)), repeat)));
+
+                    // Give the input method first crack at the event. If it
+                    // consumes the press it will drive the preedit/commit
+                    // handlers, so we must not also deliver a synthetic
+                    // `key_down` here or ordinary typing would double-fire. Text
+                    // the IM consumes and passes straight through reaches the
+                    // handler as `Key::Character` from `connect_commit`; presses
+                    // it does not consume are delivered as `key_down` below.
+                    let consumed = controler
+                        .get_current_event()
+                        .and_then(|ev| ev.downcast::<gdk::EventKey>().ok())
+                        .map(|ev| state.im_context.filter_keypress(&ev))
+                        .unwrap_or(false);
+
+                    if !consumed {
+                        let composing = state.composing.get();
+                        state.with_handler(|h|
+                            h.key_down(make_key_event(&key, keycode as u16, repeat, KeyState::Down, Some(modi), composing))
+                        );
+                    }
                 }
 
                 Inhibit(true)
@@ -643,18 +831,83 @@ impl WindowBuilder {
 
         win_state
             .key_event_controler
-            .connect_key_released(clone!(handle => move |_controler, key, _u32, modi| {
+            .connect_key_released(clone!(handle => move |_controler, key, keycode, modi| {
                 if let Some(state) = handle.state.upgrade() {
 
+                    keycodes::note_key_released(keycode as u16);
                     if state.current_keycode.get() == Some(*key) {
                         state.current_keycode.set(None);
                     }
 
+                    let composing = state.composing.get();
                     state.with_handler(|h|
-                        h.key_up(make_key_event(&key, false, KeyState::Up,Some(modi)))
+                        h.key_up(make_key_event(&key, keycode as u16, false, KeyState::Up, Some(modi), composing))
                     );
                 }
             }));
+        // The composition begins on the first preedit signal, each update
+        // replaces the preedit buffer, and commit clears it and delivers the
+        // final text.
+        win_state.im_context.connect_preedit_start(clone!(handle => move |_im| {
+            if let Some(state) = handle.state.upgrade() {
+                state.composing.set(true);
+                state.with_handler(|h| h.composition_start());
+            }
+        }));
+
+        win_state.im_context.connect_preedit_changed(clone!(handle => move |im| {
+            if let Some(state) = handle.state.upgrade() {
+                let (preedit, _attrs, cursor) = im.get_preedit_string();
+                // GTK reports the caret in characters; the handler takes bytes.
+                let cursor = preedit
+                    .char_indices()
+                    .nth(cursor.max(0) as usize)
+                    .map_or(preedit.len(), |(i, _)| i);
+                state.composing.set(true);
+                state.with_handler(|h| h.composition_update(preedit.as_str(), cursor));
+            }
+        }));
+
+        win_state.im_context.connect_preedit_end(clone!(handle => move |_im| {
+            if let Some(state) = handle.state.upgrade() {
+                // Only report the end here if `commit` hasn't already cleared the
+                // composing flag and delivered the final text; otherwise a
+                // committed composition would fire `composition_end` twice.
+                if state.composing.replace(false) {
+                    state.with_handler(|h| h.composition_end(""));
+                }
+            }
+        }));
+
+        win_state.im_context.connect_commit(clone!(handle => move |_im, text| {
+            if let Some(state) = handle.state.upgrade() {
+                let was_composing = state.composing.replace(false);
+                if was_composing {
+                    state.with_handler(|h| h.composition_end(text));
+                } else {
+                    // Ordinary (non-composed) text the IM passed straight
+                    // through, e.g. a plain dead-key-less character. Recover the
+                    // physical code, modifiers and repeat flag from the press
+                    // that produced it so the event isn't reported as an
+                    // unidentified key.
+                    let (code, mods, repeat) = state
+                        .last_key_down
+                        .get()
+                        .unwrap_or((keyboard_types::Code::Unidentified, Modifiers::empty(), false));
+                    let event = KeyEvent {
+                        key: Key::Character(text.to_string()),
+                        code,
+                        location: keyboard_types::Location::Standard,
+                        mods,
+                        repeat,
+                        is_composing: false,
+                        state: KeyState::Down,
+                    };
+                    state.with_handler(|h| h.key_down(event));
+                }
+            }
+        }));
+
         win_state
             .focus_event_controler
             .connect_enter(clone!(handle => move |_focus| {
@@ -703,6 +956,13 @@ impl WindowBuilder {
             h.scale(scale);
             h.size(size);
         });
+
+        // Controller input runs on its own poller, driven off a low-frequency
+        // GTK timeout, and is translated and dispatched through the same
+        // handler as keyboard and mouse events.
+        #[cfg(feature = "gamepad")]
+        spawn_gamepad_poller(&handle);
+
         win_state.window.show();
         Ok(handle)
     }
@@ -711,14 +971,22 @@ impl WindowBuilder {
 impl WindowState {
     #[track_caller]
     fn with_handler<T, F: FnOnce(&mut dyn WinHandler) -> T>(&self, f: F) -> Option<T> {
-        if self.invalid.try_borrow_mut().is_err() || self.surface.try_borrow_mut().is_err() {
-            error!("other RefCells were borrowed when calling into the handler");
-            return None;
+        // Only the outermost call drains the deferred/handler/idle queues. A
+        // reentrant call just queues its work (via the helper below) and returns;
+        // draining here too would re-enter while `in_handler` is still set, which
+        // re-queues the same work forever.
+        if self.in_handler.get() {
+            return self.with_handler_and_dont_check_the_other_borrows(f);
         }
 
         let ret = self.with_handler_and_dont_check_the_other_borrows(f);
 
+        // Drain work that was deferred or scheduled while we were in the
+        // handler. The reentrancy flag is already cleared at this point, so
+        // these calls can re-enter the handler cleanly.
         self.run_deferred();
+        self.run_handler_queue();
+        self.run_idle_queue();
         ret
     }
 
@@ -727,13 +995,60 @@ impl WindowState {
         &self,
         f: F,
     ) -> Option<T> {
-        match self.handler.try_borrow_mut() {
+        if self.in_handler.replace(true) {
+            // We're already inside a handler callback; entering again would be a
+            // reentrant borrow. Rather than dropping the work, queue it to run
+            // once the outer call returns. We can't produce the closure's return
+            // value synchronously, so this path yields `None`.
+            self.handler_queue.borrow_mut().push(Box::new(move |h| {
+                f(h);
+            }));
+            return None;
+        }
+        let ret = match self.handler.try_borrow_mut() {
             Ok(mut h) => Some(f(&mut **h)),
             Err(_) => {
                 error!("failed to borrow WinHandler at {}", Location::caller());
                 None
             }
+        };
+        self.in_handler.set(false);
+        ret
+    }
+
+    /// Drain handler calls that were queued because they arrived reentrantly,
+    /// running each through the handler now that the outer call has returned.
+    /// Loops so work queued by a drained call is itself drained.
+    fn run_handler_queue(&self) {
+        loop {
+            let queued: Vec<_> = std::mem::take(&mut *self.handler_queue.borrow_mut());
+            if queued.is_empty() {
+                break;
+            }
+            self.with_handler_and_dont_check_the_other_borrows(|handler| {
+                for cb in queued {
+                    cb(handler);
+                }
+            });
+        }
+    }
+
+    /// Drain any idle callbacks that have accumulated, running them through the
+    /// handler. Called when leaving a handler callback so that idle work queued
+    /// during the callback runs promptly without spinning a timer.
+    fn run_idle_queue(&self) {
+        let queue: Vec<_> = std::mem::replace(&mut *self.idle_queue.lock().unwrap(), Vec::new());
+        if queue.is_empty() {
+            return;
         }
+        self.with_handler_and_dont_check_the_other_borrows(|handler| {
+            for item in queue {
+                match item {
+                    IdleKind::Callback(it) => it.call(handler.as_any()),
+                    IdleKind::Token(it) => handler.idle(it),
+                }
+            }
+        });
     }
 
     fn resize_surface(&self, width: i32, height: i32) -> Result<(), anyhow::Error> {
@@ -847,13 +1162,37 @@ impl WindowHandle {
         }
     }
 
-    pub fn set_position(&self, position: Point) {
-        //FIXME: set_position is not a thing in gtk4
+    pub fn set_position(&self, _position: Point) {
+        // Absolute window positioning is unsupported on Wayland (clients cannot
+        // place their own surfaces), so this is a no-op there. It also isn't
+        // exposed by GTK4 on X11, hence the blanket no-op with a warning.
+        if is_wayland(self) {
+            warn!("WindowHandle::set_position is unsupported on Wayland.");
+        } else {
+            warn!("WindowHandle::set_position is unimplemented for GTK4.");
+        }
     }
 
     pub fn get_position(&self) -> Point {
-        //FIXME: get_position is not a thing in gtk4
-        Point::new(0.0, 0.0)
+        // GTK4 does not expose the absolute screen position of a client window.
+        // As the closest available anchor we report the origin of the monitor
+        // the window is on, so callers have a meaningful frame of reference
+        // rather than an unconditional `(0, 0)`.
+        self.get_monitor()
+            .map(|m| m.virtual_rect().origin())
+            .unwrap_or_else(|| Point::new(0.0, 0.0))
+    }
+
+    /// The monitor this window is currently displayed on, if it can be
+    /// determined.
+    pub fn get_monitor(&self) -> Option<crate::screen::Monitor> {
+        let state = self.state.upgrade()?;
+        let display = state.window.get_display();
+        let surface = state.window.get_native()?.get_surface()?;
+        let monitor = display.get_monitor_at_surface(&surface)?;
+        let primary = display.get_primary_monitor();
+        let is_primary = primary.as_ref() == Some(&monitor);
+        Some(super::screen::translate_gdk_monitor(monitor, is_primary))
     }
 
     pub fn content_insets(&self) -> Insets {
@@ -862,17 +1201,34 @@ impl WindowHandle {
     }
 
     pub fn set_level(&self, level: WindowLevel) {
-        //FIXME: Window hints are not a thing in gtk4
-        // if let Some(state) = self.state.upgrade() {
-        //     let hint = match level {
-        //         WindowLevel::AppWindow => WindowTypeHint::Normal,
-        //         WindowLevel::Tooltip => WindowTypeHint::Tooltip,
-        //         WindowLevel::DropDown => WindowTypeHint::DropdownMenu,
-        //         WindowLevel::Modal => WindowTypeHint::Dialog,
-        //     };
-
-        //     state.window.set_type_hint(hint);
-        // }
+        // GTK4 dropped X11-style window type hints, so we express the window's
+        // role through the mechanisms GTK4 does offer: transient parents,
+        // modality, and decoration state.
+        if let Some(state) = self.state.upgrade() {
+            let self_window: &gtk::Window = state.window.upcast_ref();
+            let parent = state
+                .window
+                .get_application()
+                .and_then(|app| app.get_active_window())
+                .filter(|w| w != self_window);
+            match level {
+                WindowLevel::AppWindow => {
+                    state.window.set_transient_for(gtk::Window::NONE);
+                    state.window.set_modal(false);
+                }
+                WindowLevel::Tooltip | WindowLevel::DropDown => {
+                    // Anchor popups to their owning window, undecorated and
+                    // non-resizable so they stack above it like a real popup.
+                    state.window.set_transient_for(parent.as_ref());
+                    state.window.set_decorated(false);
+                    state.window.set_resizable(false);
+                }
+                WindowLevel::Modal => {
+                    state.window.set_transient_for(parent.as_ref());
+                    state.window.set_modal(true);
+                }
+            }
+        }
     }
 
     pub fn set_size(&self, size: Size) {
@@ -884,9 +1240,10 @@ impl WindowHandle {
 
     pub fn get_size(&self) -> Size {
         if let Some(state) = self.state.upgrade() {
-            //FIXME: getting the window size is actually impossible!!!
-            let (x, y) = state.window.get_default_size();
-            Size::new(x as f64, y as f64)
+            // `area` is kept in sync with the drawing area's allocation, so it
+            // reflects the live window size in display points rather than the
+            // stale default size.
+            state.area.get().size_dp()
         } else {
             warn!("Could not get size for GTK window");
             Size::new(0., 0.)
@@ -899,26 +1256,37 @@ impl WindowHandle {
         if let Some(state) = self.state.upgrade() {
             match (size_state, cur_size_state) {
                 (s1, s2) if s1 == s2 => (),
-                (MAXIMIZED, _) => state.window.maximize(),
+                (MAXIMIZED, _) => {
+                    // Remember the current size so we can return to it later.
+                    // The true surface size is reported to the handler from the
+                    // first draw after the maximize takes effect, so we don't
+                    // force a size here (which would cause a restore-size flash).
+                    state.restore_size.set(state.area.get().size_dp());
+                    state.window.maximize();
+                }
                 (MINIMIZED, _) => state.window.minimize(),
-                (RESTORED, MAXIMIZED) => state.window.unmaximize(),
+                (RESTORED, MAXIMIZED) => {
+                    state.window.unmaximize();
+                    let restore = state.restore_size.get();
+                    state
+                        .window
+                        .set_default_size(restore.width as i32, restore.height as i32);
+                }
                 (RESTORED, MINIMIZED) => state.window.unminimize(),
                 (RESTORED, RESTORED) => (), // Unreachable
             }
-
-            state.window.unmaximize();
         }
     }
 
     pub fn get_window_state(&self) -> window::WindowState {
-        use window::WindowState::{MAXIMIZED, MINIMIZED, RESTORED};
+        use window::WindowState::{MAXIMIZED, RESTORED};
         if let Some(state) = self.state.upgrade() {
             if state.window.is_maximized() {
-                 MAXIMIZED
+                MAXIMIZED
             } else {
-                 MINIMIZED
+                RESTORED
             }
-        }else{
+        } else {
             RESTORED
         }
     }
@@ -991,17 +1359,34 @@ impl WindowHandle {
     pub fn set_cursor(&mut self, cursor: &Cursor) {
         if let Some(state) = self.state.upgrade() {
             let cursor = make_gdk_cursor(cursor);
+            // Set on both the toplevel and the drawing area, so the cursor
+            // takes effect over our content and not just the window chrome.
             state.window.set_cursor(cursor.as_ref());
+            state.drawing_area.set_cursor(cursor.as_ref());
         }
     }
 
     pub fn make_cursor(&self, desc: &CursorDesc) -> Option<Cursor> {
         if let Some(state) = self.state.upgrade() {
-            // TODO: gtk::Pixbuf expects unpremultiplied alpha. We should convert.
+            let _ = &state;
             let has_alpha = !matches!(desc.image.format(), ImageFormat::Rgb);
             let bytes_per_pixel = desc.image.format().bytes_per_pixel();
+            // GDK's `Pixbuf` expects straight (un-premultiplied) alpha, whereas
+            // piet image data is premultiplied. Divide each color channel by
+            // its alpha so translucent cursors aren't rendered too dark.
+            let mut pixels = desc.image.raw_pixels().to_owned();
+            if has_alpha {
+                for px in pixels.chunks_exact_mut(bytes_per_pixel) {
+                    let alpha = px[3];
+                    if alpha != 0 && alpha != 255 {
+                        for c in &mut px[0..3] {
+                            *c = ((*c as u16 * 255) / alpha as u16).min(255) as u8;
+                        }
+                    }
+                }
+            }
             let pixbuf = Pixbuf::from_mut_slice(
-                desc.image.raw_pixels().to_owned(),
+                pixels,
                 Colorspace::Rgb,
                 has_alpha,
                 // bits_per_sample
@@ -1074,8 +1459,9 @@ impl WindowHandle {
             if first_child.is::<gtk::PopoverMenuBar>() {
                 vbox.remove(first_child);
             }
-            let menubar = menu.into_gtk_menubar(&self);
-            vbox.prepend(&menubar);
+            if let Some(menubar) = menu.into_gtk_menubar(&self) {
+                vbox.prepend(&menubar);
+            }
         }
     }
 
@@ -1092,10 +1478,16 @@ impl WindowHandle {
     }
 }
 
-// WindowState needs to be Send + Sync so it can be passed into glib closures.
-// TODO: can we localize the unsafety more? Glib's idle loop always runs on the main thread,
-// and we always construct the WindowState on the main thread, so it should be ok (and also
-// WindowState isn't a public type).
+// SAFETY: `WindowState` is never actually touched off the main thread. It is
+// constructed on the GTK main thread and its GTK/GDK members are only ever
+// accessed from there. The single reason it must be `Send + Sync` is that
+// `IdleHandle` (the one part of the API callable from other threads) captures
+// an `Arc<WindowState>` into the `glib::idle_add` source; glib requires that
+// closure to be `Send`, and `Arc<T>: Send` demands `T: Send + Sync`. The Arc is
+// only ever *run* back on the main thread — `run_idle` asserts this — so no GTK
+// member is read or written across threads. The cross-thread surface of
+// `IdleHandle` itself is limited to the already-`Send` `Arc<Mutex<_>>` idle
+// queue; everything else stays main-thread-local.
 unsafe impl Send for WindowState {}
 unsafe impl Sync for WindowState {}
 
@@ -1134,48 +1526,211 @@ impl IdleHandle {
     }
 }
 
+/// Start polling the gamepad subsystem for this window, translating controller
+/// events and dispatching them through the handler on the GTK main thread. The
+/// poll stops on its own once the window has been dropped.
+#[cfg(feature = "gamepad")]
+fn spawn_gamepad_poller(handle: &WindowHandle) {
+    use crate::gamepad::GamepadPoller;
+
+    let mut poller = match GamepadPoller::new() {
+        Some(poller) => poller,
+        None => return,
+    };
+    let handle = handle.clone();
+    gtk::glib::timeout_add_local(std::time::Duration::from_millis(8), move || {
+        match handle.state.upgrade() {
+            Some(state) => {
+                for event in poller.poll() {
+                    state.with_handler(|h| h.gamepad_event(event));
+                }
+                gtk::glib::source::Continue(true)
+            }
+            // The window is gone, so tear the poller down with it.
+            None => gtk::glib::source::Continue(false),
+        }
+    });
+}
+
 fn run_idle(state: &Arc<WindowState>) -> gtk::glib::source::Continue {
     util::assert_main_thread();
-    let result = state.with_handler(|handler| {
-        let queue: Vec<_> = std::mem::replace(&mut state.idle_queue.lock().unwrap(), Vec::new());
+    // If the handler happens to be busy right now, the idle work is moved onto
+    // the handler queue and run when the in-flight handler callback returns (see
+    // `WindowState::with_handler`), so there is no need to reschedule on a timer
+    // and burn CPU waiting for the borrow.
+    state.run_idle_queue();
+    gtk::glib::source::Continue(false)
+}
+
+/// Build a client-side decoration bar for `window`: a draggable title region
+/// plus minimize/maximize/close buttons, used when the server titlebar is
+/// suppressed. `resizable` controls whether the maximize button is shown.
+fn build_client_side_decorations(
+    window: &ApplicationWindow,
+    title: &str,
+    resizable: bool,
+) -> gtk::WindowHandle {
+    let bar = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+    bar.set_hexpand(true);
+
+    // The draggable title area. Wrapping it in a `gtk::WindowHandle` gives us
+    // move-drag and double-click-to-maximize for free, mirroring a real
+    // titlebar.
+    let label = gtk::Label::new(Some(title));
+    label.set_hexpand(true);
+    bar.append(&label);
+
+    if resizable {
+        let maximize = gtk::Button::from_icon_name(Some("window-maximize-symbolic"));
+        maximize.connect_clicked(clone!(window => move |_| {
+            if window.is_maximized() {
+                window.unmaximize();
+            } else {
+                window.maximize();
+            }
+        }));
+        bar.append(&maximize);
+    }
+
+    let minimize = gtk::Button::from_icon_name(Some("window-minimize-symbolic"));
+    minimize.connect_clicked(clone!(window => move |_| window.minimize()));
+    bar.append(&minimize);
+
+    let close = gtk::Button::from_icon_name(Some("window-close-symbolic"));
+    close.connect_clicked(clone!(window => move |_| window.close()));
+    bar.append(&close);
+
+    if resizable {
+        // A bare undecorated window has no edges the compositor will grab, so
+        // install our own edge/corner resize handles.
+        install_resize_grips(window);
+    }
+
+    let handle = gtk::WindowHandle::new();
+    handle.set_child(Some(&bar));
+    handle
+}
 
-        for item in queue {
-            match item {
-                IdleKind::Callback(it) => it.call(handler.as_any()),
-                IdleKind::Token(it) => handler.idle(it),
+/// Attach an edge-aware drag gesture to `window` so a press within a few pixels
+/// of an edge or corner starts an interactive resize in that direction. Drags
+/// that start away from the edges are left alone so window content still
+/// receives them.
+fn install_resize_grips(window: &ApplicationWindow) {
+    // How close to an edge, in display points, counts as a resize grip.
+    const GRIP: f64 = 8.0;
+
+    let drag = gtk::GestureDrag::new();
+    // Watch in the capture phase so the grip wins over child widgets, but deny
+    // the sequence when the press isn't on an edge so they still get it.
+    drag.set_propagation_phase(gtk::PropagationPhase::Capture);
+    drag.connect_drag_begin(clone!(window => move |gesture, x, y| {
+        let width = window.get_width() as f64;
+        let height = window.get_height() as f64;
+        let edge = resize_edge(x, y, width, height, GRIP);
+        let edge = match edge {
+            Some(edge) => edge,
+            None => {
+                gesture.set_state(gtk::EventSequenceState::Denied);
+                return;
             }
+        };
+        let toplevel = window
+            .get_native()
+            .and_then(|native| native.get_surface())
+            .and_then(|surface| surface.downcast::<gdk::Toplevel>().ok());
+        if let Some(toplevel) = toplevel {
+            gesture.set_state(gtk::EventSequenceState::Claimed);
+            let device = gesture.get_device();
+            let button = gesture.get_current_button() as i32;
+            toplevel.begin_resize(edge, device.as_ref(), button, x, y);
         }
-    });
+    }));
+    window.add_controller(&drag);
+}
+
+/// Pick the resize edge for a press at `(x, y)` within a `width` x `height`
+/// window, or `None` if the press isn't within `grip` of any edge.
+fn resize_edge(x: f64, y: f64, width: f64, height: f64, grip: f64) -> Option<gdk::SurfaceEdge> {
+    let west = x <= grip;
+    let east = x >= width - grip;
+    let north = y <= grip;
+    let south = y >= height - grip;
+    Some(match (north, south, west, east) {
+        (true, _, true, _) => gdk::SurfaceEdge::NorthWest,
+        (true, _, _, true) => gdk::SurfaceEdge::NorthEast,
+        (_, true, true, _) => gdk::SurfaceEdge::SouthWest,
+        (_, true, _, true) => gdk::SurfaceEdge::SouthEast,
+        (true, ..) => gdk::SurfaceEdge::North,
+        (_, true, ..) => gdk::SurfaceEdge::South,
+        (_, _, true, _) => gdk::SurfaceEdge::West,
+        (_, _, _, true) => gdk::SurfaceEdge::East,
+        _ => return None,
+    })
+}
 
-    if result.is_none() {
-        warn!("Delaying idle callbacks because the handler is borrowed.");
-        // Keep trying to reschedule this idle callback, because we haven't had a chance
-        // to empty the idle queue. Returning gtk::glib::source::Continue(true) achieves this but
-        // causes 100% CPU usage, apparently because glib likes to call us back very quickly.
-        let state = Arc::clone(state);
-        gtk::glib::timeout_add(std::time::Duration::from_millis(16), move || run_idle(&state));
+/// Translate a raw scroll delta into a druid `wheel_delta`, handling discrete
+/// and precise scroll sources differently.
+///
+/// Discrete (notch-based) wheels report integer steps, which we scale to the
+/// Microsoft 120-units-per-tick convention the other backends use. Precise
+/// trackpads report fractional pixel deltas, which we forward converted from
+/// device pixels to display points.
+fn wheel_delta(dx: f64, dy: f64, is_precise: bool, scale: Scale) -> (f64, f64) {
+    if is_precise {
+        // Precise (touchpad) deltas arrive in physical pixels; convert to
+        // display points the same way positions go through `to_dp`, i.e. divide
+        // by the scale rather than multiply by it.
+        (dx / scale.x(), dy / scale.y())
+    } else {
+        (dx * 120.0, dy * 120.0)
     }
-    gtk::glib::source::Continue(false)
+}
+
+/// Whether the window is running on the Wayland GDK backend.
+fn is_wayland(handle: &WindowHandle) -> bool {
+    handle
+        .state
+        .upgrade()
+        .map(|state| {
+            state
+                .window
+                .get_display()
+                .is::<gdk::wayland::WaylandDisplay>()
+        })
+        .unwrap_or(false)
 }
 
 fn make_gdk_cursor(cursor: &Cursor) -> Option<gdk::Cursor> {
     if let Cursor::Custom(custom) = cursor {
         Some(custom.0.clone())
     } else {
-        gdk::Cursor::from_name(
-            match cursor {
-                // cursor name values from https://www.w3.org/TR/css-ui-3/#cursor
-                Cursor::Arrow => "default",
-                Cursor::IBeam => "text",
-                Cursor::Crosshair => "crosshair",
-                Cursor::OpenHand => "grab",
-                Cursor::NotAllowed => "not-allowed",
-                Cursor::ResizeLeftRight => "ew-resize",
-                Cursor::ResizeUpDown => "ns-resize",
-                Cursor::Custom(_) => unreachable!(),
-            },
-            None,
-        )
+        // cursor name values from https://www.w3.org/TR/css-ui-3/#cursor
+        let name = match cursor {
+            Cursor::Arrow => "default",
+            Cursor::IBeam => "text",
+            Cursor::Crosshair => "crosshair",
+            Cursor::OpenHand => "grab",
+            Cursor::NotAllowed => "not-allowed",
+            Cursor::ResizeLeftRight => "ew-resize",
+            Cursor::ResizeUpDown => "ns-resize",
+            Cursor::ResizeColumn => "col-resize",
+            Cursor::ResizeRow => "row-resize",
+            Cursor::ResizeNwSe => "nwse-resize",
+            Cursor::ResizeNeSw => "nesw-resize",
+            Cursor::Pointer => "pointer",
+            Cursor::AllScroll => "all-scroll",
+            Cursor::Move => "move",
+            Cursor::Wait => "wait",
+            Cursor::Progress => "progress",
+            Cursor::Help => "help",
+            Cursor::Cell => "cell",
+            Cursor::ZoomIn => "zoom-in",
+            Cursor::ZoomOut => "zoom-out",
+            Cursor::Custom(_) => unreachable!(),
+        };
+        // A named cursor may not exist in the active theme; fall back to the
+        // default arrow rather than leaving the window with no cursor.
+        gdk::Cursor::from_name(name, gdk::Cursor::from_name("default", None).as_ref())
     }
 }
 
@@ -1250,23 +1805,30 @@ fn get_modifiers(modifiers: Option<gdk::ModifierType>) -> Modifiers {
     result
 }
 
-fn make_key_event(raw_key: &GDKKey, repeat: bool, state: KeyState, modi: Option<ModifierType>) -> KeyEvent {
-    let text = raw_key.to_unicode();
+fn make_key_event(
+    raw_key: &GDKKey,
+    hw_keycode: u16,
+    repeat: bool,
+    state: KeyState,
+    modi: Option<ModifierType>,
+    is_composing: bool,
+) -> KeyEvent {
     let mods = get_modifiers(modi);
-    let key = keycodes::raw_key_to_key(raw_key).unwrap_or_else(|| {
-        if let Some(c) = text {
-            if c >= ' ' && c != '\x7f' {
-                Key::Character(c.to_string())
-            } else {
-                Key::Unidentified
-            }
-        } else {
-            Key::Unidentified
-        }
-    });
-    let code = keycodes::hardware_keycode_to_code(raw_key);
+    // Logical key respects the active layout, falling back to the keysym's
+    // Unicode value for anything the named-key table doesn't enumerate. Composed
+    // text (dead-key results, layout-shifted characters) is surfaced here as the
+    // `Key::Character` value rather than on a separate field: `KeyEvent` models
+    // the logical key as the produced text, so there is nowhere else for it to
+    // go, and IME-composed runs arrive through the `composition_*` callbacks.
+    let key = keycodes::logical_key(raw_key);
+    // `code` is the physical key position, so it goes through the remapped
+    // hardware scancode; only if that table has no entry do we fall back to the
+    // keysym, which would otherwise ignore an OS-level scancode swap.
+    let code = match keycodes::code_for_hardware_keycode(hw_keycode) {
+        keyboard_types::Code::Unidentified => keycodes::hardware_keycode_to_code(raw_key),
+        code => code,
+    };
     let location = keycodes::raw_key_to_location(raw_key);
-    let is_composing = false;
 
     KeyEvent {
         key,
@@ -0,0 +1,93 @@
+// Copyright 2020 The Druid Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Types and functions for describing the physical displays available to the
+//! application.
+//!
+//! Coordinates are in the virtual screen space: a single rectangle spanning all
+//! monitors, in display points, with the origin at the top-left of the primary
+//! monitor.
+
+use crate::backend::screen as backend;
+use crate::kurbo::Rect;
+
+/// A physical monitor attached to the system.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Monitor {
+    primary: bool,
+    rect: Rect,
+    // The working area excludes panels, docks and other system furniture.
+    work_rect: Rect,
+    scale_factor: f64,
+    refresh_rate: f64,
+}
+
+impl Monitor {
+    /// Create a `Monitor` spanning `rect`, whose usable region is `work_rect`.
+    ///
+    /// The scale factor defaults to `1.0` and the refresh rate to `0.0`
+    /// (unknown); backends fill these in with [`Monitor::set_scale_factor`] and
+    /// [`Monitor::set_refresh_rate`].
+    pub fn new(primary: bool, rect: Rect, work_rect: Rect) -> Monitor {
+        Monitor {
+            primary,
+            rect,
+            work_rect,
+            scale_factor: 1.0,
+            refresh_rate: 0.0,
+        }
+    }
+
+    /// Whether this is the primary monitor.
+    pub fn is_primary(&self) -> bool {
+        self.primary
+    }
+
+    /// The monitor's rectangle in virtual screen coordinates.
+    pub fn virtual_rect(&self) -> Rect {
+        self.rect
+    }
+
+    /// The monitor's usable rectangle, excluding panels and docks, in virtual
+    /// screen coordinates.
+    pub fn virtual_work_rect(&self) -> Rect {
+        self.work_rect
+    }
+
+    /// The ratio of physical pixels to display points for this monitor.
+    pub fn scale_factor(&self) -> f64 {
+        self.scale_factor
+    }
+
+    /// Set the monitor's scale factor.
+    pub fn set_scale_factor(&mut self, scale_factor: f64) {
+        self.scale_factor = scale_factor;
+    }
+
+    /// The monitor's refresh rate in hertz, or `0.0` if the backend could not
+    /// report it.
+    pub fn refresh_rate(&self) -> f64 {
+        self.refresh_rate
+    }
+
+    /// Set the monitor's refresh rate, in hertz.
+    pub fn set_refresh_rate(&mut self, refresh_rate: f64) {
+        self.refresh_rate = refresh_rate;
+    }
+}
+
+/// Returns the list of monitors attached to the system.
+pub fn get_monitors() -> Vec<Monitor> {
+    backend::get_monitors()
+}
@@ -0,0 +1,222 @@
+// Copyright 2019 The Druid Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Platform independent window types.
+
+use std::any::Any;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::dialog::FileInfo;
+use crate::keyboard::KeyEvent;
+use crate::kurbo::{Point, Size};
+use crate::mouse::MouseEvent;
+use crate::piet::Piet;
+use crate::region::Region;
+use crate::scale::Scale;
+
+pub use crate::backend::window::WindowHandle;
+
+/// A token that uniquely identifies a running timer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TimerToken(u64);
+
+/// A token that uniquely identifies a idle schedule.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct IdleToken(usize);
+
+/// A token that uniquely identifies a file dialog request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FileDialogToken(u64);
+
+impl TimerToken {
+    /// A token that does not correspond to any timer.
+    pub const INVALID: TimerToken = TimerToken(0);
+
+    /// Generate a new, unique token.
+    pub fn next() -> TimerToken {
+        static COUNTER: AtomicU64 = AtomicU64::new(1);
+        TimerToken(COUNTER.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl IdleToken {
+    /// Construct an `IdleToken` from a raw identifier.
+    pub fn new(raw: usize) -> IdleToken {
+        IdleToken(raw)
+    }
+}
+
+impl FileDialogToken {
+    /// Generate a new, unique token.
+    pub fn next() -> FileDialogToken {
+        static COUNTER: AtomicU64 = AtomicU64::new(1);
+        FileDialogToken(COUNTER.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// Levels in the window system - Z order for display purposes.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WindowLevel {
+    /// A normal top-level application window.
+    AppWindow,
+    /// A window that should stay above app windows, e.g. a tooltip.
+    Tooltip,
+    /// A window used to display a dropdown menu or combo box.
+    DropDown,
+    /// A modal dialog, blocking interaction with its parent.
+    Modal,
+}
+
+/// Contains the different states a Window can be in.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WindowState {
+    /// The window is displayed at its normal size.
+    RESTORED,
+    /// The window fills the screen.
+    MAXIMIZED,
+    /// The window is hidden to the taskbar/dock.
+    MINIMIZED,
+}
+
+/// App behavior, supplied by the app.
+///
+/// Many of the "window procedure" messages map to calls to this trait.
+/// The methods are non-mutable, even though the window procedure frequently
+/// needs to mutate state, because state is held behind a shared reference.
+#[allow(unused_variables)]
+pub trait WinHandler {
+    /// Provide the handler with a handle to the window so that it can
+    /// invalidate or make other requests.
+    ///
+    /// This method passes the `WindowHandle` directly, because the handler may
+    /// wish to stash it.
+    fn connect(&mut self, handle: &WindowHandle);
+
+    /// Called when the size of the window has changed, in display points.
+    fn size(&mut self, size: Size) {}
+
+    /// Called when the [scale](crate::scale::Scale) of the window has changed.
+    fn scale(&mut self, scale: Scale) {}
+
+    /// Request the handler to prepare to paint the window contents. In
+    /// particular, if there are any regions that need to be repainted, the
+    /// handler should invalidate those regions by calling
+    /// [`WindowHandle::invalidate_rect`] or [`WindowHandle::invalidate`].
+    fn prepare_paint(&mut self) {}
+
+    /// Called when the window contents need to be painted.
+    ///
+    /// It is the handler's responsibility to ensure that the entire `invalid`
+    /// region is painted.
+    fn paint(&mut self, piet: &mut Piet, invalid: &Region);
+
+    /// Called when the menu has a command.
+    fn command(&mut self, id: u32) {}
+
+    /// Called when a "Save As" dialog is closed.
+    ///
+    /// The token is the one passed to [`WindowHandle::save_as`]; if the dialog
+    /// was cancelled `file` is `None`.
+    fn save_as(&mut self, token: FileDialogToken, file: Option<FileInfo>) {}
+
+    /// Called when an "Open" dialog is closed.
+    ///
+    /// The token is the one passed to [`WindowHandle::open_file`]; if the
+    /// dialog was cancelled `file` is `None`.
+    fn open_file(&mut self, token: FileDialogToken, file: Option<FileInfo>) {}
+
+    /// Called on a key down event.
+    ///
+    /// Return `true` if the event is handled.
+    fn key_down(&mut self, event: KeyEvent) -> bool {
+        false
+    }
+
+    /// Called when a key is released.
+    fn key_up(&mut self, event: KeyEvent) {}
+
+    /// Called on a mouse wheel event.
+    fn wheel(&mut self, event: &MouseEvent) {}
+
+    /// Called when the mouse moves.
+    fn mouse_move(&mut self, event: &MouseEvent) {}
+
+    /// Called on mouse button down.
+    fn mouse_down(&mut self, event: &MouseEvent) {}
+
+    /// Called on mouse button up.
+    fn mouse_up(&mut self, event: &MouseEvent) {}
+
+    /// Called when the mouse cursor has left the application window.
+    fn mouse_leave(&mut self) {}
+
+    /// Called when a drag-and-drop operation enters the window, in display
+    /// points.
+    fn drag_enter(&mut self, pos: Point) {}
+
+    /// Called as the pointer moves during a drag-and-drop operation, in display
+    /// points.
+    fn drag_move(&mut self, pos: Point) {}
+
+    /// Called when a drag-and-drop operation leaves the window without dropping.
+    fn drag_leave(&mut self) {}
+
+    /// Called when a file is dropped onto the window at `pos`, in display
+    /// points.
+    fn dropped_file(&mut self, file: FileInfo, pos: Point) {}
+
+    /// Called when text is dropped onto the window at `pos`, in display points.
+    fn dropped_text(&mut self, text: &str, pos: Point) {}
+
+    /// Called when an input-method composition (preedit) session begins.
+    fn composition_start(&mut self) {}
+
+    /// Called as the composition preedit changes. `text` is the current preedit
+    /// string and `cursor` the caret offset within it, in bytes.
+    fn composition_update(&mut self, text: &str, cursor: usize) {}
+
+    /// Called when a composition session ends, delivering the committed `text`
+    /// (empty if the composition was cancelled).
+    fn composition_end(&mut self, text: &str) {}
+
+    /// Called for each gamepad/controller input event, on the same thread as
+    /// the keyboard and mouse handlers.
+    fn gamepad_event(&mut self, event: crate::gamepad::GamepadEvent) {}
+
+    /// Called on a timer event.
+    fn timer(&mut self, token: TimerToken) {}
+
+    /// Called when this window becomes the focused window.
+    fn got_focus(&mut self) {}
+
+    /// Called when this window stops being the focused window.
+    fn lost_focus(&mut self) {}
+
+    /// Called when the window is closing, in response to a user request.
+    ///
+    /// This allows handlers to attempt to cancel the close, for example to
+    /// prompt the user to save unsaved work.
+    fn request_close(&mut self) {}
+
+    /// Called when the window is being destroyed. Note that this happens
+    /// earlier in the sequence than drop (at WM_DESTROY, while the GUI window
+    /// is still present).
+    fn destroy(&mut self) {}
+
+    /// Called when a idle token is requested by `IdleHandle::add_idle_token`.
+    fn idle(&mut self, token: IdleToken) {}
+
+    /// Get a reference to the handler state. Used mostly by idle handlers.
+    fn as_any(&mut self) -> &mut dyn Any;
+}